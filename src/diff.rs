@@ -0,0 +1,319 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde_json::json;
+
+use crate::{error::{self, Error}, extract::{self, IpLocation}, ip_tool};
+
+// 比较时统一使用 IPv6 数据集的编号，IPv4 地址放在 ::ffff:0:0/96，IPv4 和 IPv6 数据集之间也可以比较
+
+// 一段地址从 from 国家变为 to 国家，None 表示不在数据中（或国家代码为 "-"）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub start: u128,
+    pub end: u128,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CountryDiff {
+    pub added: u128,
+    pub removed: u128,
+}
+
+#[derive(Debug, Default)]
+pub struct Diff {
+    pub changes: Vec<Change>,
+    pub countries: BTreeMap<String, CountryDiff>,
+}
+
+// CSV 中的行转换为按起始地址排序的 (start, end, country_code)
+pub fn country_ranges(list: &[IpLocation]) -> error::Result<Vec<(u128, u128, String)>> {
+    let mut result = vec![];
+    for row in list.iter().filter(|x| x.country_code != "-") {
+        let (start, end) = row.range()?;
+        result.push((start, end, row.country_code.clone()));
+    }
+    // 与 lookup 一致，出现超出 IPv4 的地址说明是 IPv6 数据集
+    if result.iter().all(|x| x.1 <= u32::MAX as u128) {
+        result.iter_mut().for_each(|x| *x = (x.0 | ip_tool::MAPPED, x.1 | ip_tool::MAPPED, std::mem::take(&mut x.2)));
+    }
+    result.sort();
+    Ok(result)
+}
+
+fn parse_entry(entry: &str) -> Option<(u128, u128)> {
+    let entry = entry.trim();
+    if entry.contains('/') {
+        if !entry.contains(':') {
+            let (start, end) = ip_tool::cidr_to_range_ipv4(entry)?;
+            return Some((start as u128 | ip_tool::MAPPED, end as u128 | ip_tool::MAPPED));
+        }
+        return ip_tool::cidr_to_range_ipv6(entry);
+    }
+    let (start, end) = entry.split_once('-')?;
+    let start = ip_tool::ip_to_number(start.trim().parse().ok()?, true)?;
+    let end = ip_tool::ip_to_number(end.trim().parse().ok()?, true)?;
+    if start > end {
+        return None;
+    }
+    Some((start, end))
+}
+
+// 读取生成的 proxifier.txt、clash.txt 等输出文件，规则中的地址都标记为 label
+pub fn output_ranges(content: &str, label: &str) -> error::Result<Vec<(u128, u128, String)>> {
+    let mut entries = vec![];
+    for line in content.lines() {
+        let line = line.trim();
+        if let Some(targets) = line.split_once("<Targets>").and_then(|(_, x)| x.split_once("</Targets>")) {
+            entries.extend(targets.0.split(';').filter(|x| !x.trim().is_empty()));
+            continue;
+        }
+        // ip -batch 使用的路由表
+        if let Some(cidr) = line.strip_prefix("route replace ").and_then(|x| x.split_whitespace().next()) {
+            entries.push(cidr);
+            continue;
+        }
+        let line = line.trim_start_matches('-').trim();
+        match line.split(',').collect::<Vec<_>>()[..] {
+            [kind, cidr, ..] if kind.starts_with("IP-CIDR") => entries.push(cidr),
+            // 每行一个 CIDR 的列表和 sing-box 的 rule-set
+            _ => {
+                let entry = line.trim_matches(['"', ',']);
+                if parse_entry(entry).is_some() {
+                    entries.push(entry);
+                }
+            },
+        }
+    }
+    let mut result = vec![];
+    for entry in entries {
+        let (start, end) = parse_entry(entry).ok_or_else(|| Error::Parse(format!("invalid range in output: {}", entry)))?;
+        result.push((start, end, label.to_string()));
+    }
+    result.sort();
+    Ok(result)
+}
+
+// 按起始地址排序的区间中 number 所在的国家，index 只会向后移动
+fn country_at<'a>(list: &'a [(u128, u128, String)], index: &mut usize, number: u128) -> Option<&'a str> {
+    while *index < list.len() && list[*index].1 < number {
+        *index += 1;
+    }
+    list.get(*index).filter(|x| x.0 <= number).map(|x| x.2.as_str())
+}
+
+pub fn diff(old: &[(u128, u128, String)], new: &[(u128, u128, String)]) -> Diff {
+    // 所有区间的边界把地址空间切成小段，每一小段在新旧数据中的国家都是确定的
+    let mut bounds = BTreeSet::new();
+    for &(start, end, _) in old.iter().chain(new) {
+        bounds.insert(start);
+        if let Some(x) = end.checked_add(1) {
+            bounds.insert(x);
+        }
+    }
+    let bounds = bounds.into_iter().collect::<Vec<_>>();
+    let mut result = Diff::default();
+    let (mut old_index, mut new_index) = (0, 0);
+    for (i, &start) in bounds.iter().enumerate() {
+        let end = bounds.get(i + 1).map(|x| x - 1).unwrap_or(u128::MAX);
+        let from = country_at(old, &mut old_index, start);
+        let to = country_at(new, &mut new_index, start);
+        if from == to {
+            continue;
+        }
+        let size = (end - start).saturating_add(1);
+        if let Some(x) = from {
+            let country = result.countries.entry(x.to_string()).or_default();
+            country.removed = country.removed.saturating_add(size);
+        }
+        if let Some(x) = to {
+            let country = result.countries.entry(x.to_string()).or_default();
+            country.added = country.added.saturating_add(size);
+        }
+        match result.changes.last_mut() {
+            Some(last) if last.end.checked_add(1) == Some(start) && last.from.as_deref() == from && last.to.as_deref() == to => last.end = end,
+            _ => result.changes.push(Change {
+                start,
+                end,
+                from: from.map(|x| x.to_string()),
+                to: to.map(|x| x.to_string()),
+            }),
+        }
+    }
+    result
+}
+
+fn range_cidrs(start: u128, end: u128) -> Vec<String> {
+    let (from, to) = extract::to_ipv6_list(&[(start, end)]).remove(0);
+    let cidrs = if !to.contains(':') {
+        ip_tool::ipv4_to_cidr(&from, &to)
+    } else {
+        ip_tool::ipv6_to_cidr(&from, &to)
+    };
+    cidrs.unwrap_or_default()
+}
+
+fn net(country: &CountryDiff) -> String {
+    if country.added >= country.removed {
+        format!("+{}", country.added - country.removed)
+    } else {
+        format!("-{}", country.removed - country.added)
+    }
+}
+
+impl Diff {
+    pub fn moved(&self) -> impl Iterator<Item = &Change> {
+        self.changes.iter().filter(|x| x.from.is_some() && x.to.is_some())
+    }
+
+    pub fn added(&self) -> impl Iterator<Item = &Change> {
+        self.changes.iter().filter(|x| x.from.is_none())
+    }
+
+    pub fn removed(&self) -> impl Iterator<Item = &Change> {
+        self.changes.iter().filter(|x| x.to.is_none())
+    }
+
+    // 一行摘要，用于 daemon 的运行日志
+    pub fn summary_line(&self) -> String {
+        let (added, removed) = self.countries.values().fold((0_u128, 0_u128), |(a, r), x| (a.saturating_add(x.added), r.saturating_add(x.removed)));
+        format!("{} ranges changed ({} moved, {} added, {} removed), +{} -{} addresses",
+            self.changes.len(), self.moved().count(), self.added().count(), self.removed().count(), added, removed)
+    }
+
+    // 可读的报告，每一类变化最多列出 limit 段
+    pub fn format_text(&self, limit: usize) -> String {
+        let mut result = format!("{}\n", self.summary_line());
+        if !self.countries.is_empty() {
+            result.push_str(&format!("\n{:<8}{:>24}{:>24}{:>24}\n", "country", "added", "removed", "net"));
+            for (code, x) in &self.countries {
+                result.push_str(&format!("{:<8}{:>24}{:>24}{:>24}\n", code, x.added, x.removed, net(x)));
+            }
+        }
+        let sections: [(&str, Vec<&Change>); 3] = [("moved", self.moved().collect()), ("added", self.added().collect()), ("removed", self.removed().collect())];
+        for (title, changes) in sections {
+            if changes.is_empty() {
+                continue;
+            }
+            result.push_str(&format!("\n{}:\n", title));
+            for x in changes.iter().take(limit) {
+                let from = x.from.as_deref().unwrap_or("-");
+                let to = x.to.as_deref().unwrap_or("-");
+                result.push_str(&format!("  {} {} -> {}\n", range_cidrs(x.start, x.end).join(" "), from, to));
+            }
+            if changes.len() > limit {
+                result.push_str(&format!("  ... and {} more\n", changes.len() - limit));
+            }
+        }
+        result
+    }
+
+    // 地址个数可能超出 JSON 数字的范围，统一输出为字符串
+    pub fn to_json(&self) -> serde_json::Value {
+        let countries = self.countries.iter().map(|(code, x)| json!({
+            "country_code": code,
+            "added": x.added.to_string(),
+            "removed": x.removed.to_string(),
+            "net": net(x),
+        })).collect::<Vec<_>>();
+        let changes = self.changes.iter().map(|x| {
+            let (start, end) = extract::to_ipv6_list(&[(x.start, x.end)]).remove(0);
+            json!({
+                "start": start,
+                "end": end,
+                "from": x.from,
+                "to": x.to,
+                "cidrs": range_cidrs(x.start, x.end),
+            })
+        }).collect::<Vec<_>>();
+        json!({
+            "summary": {
+                "changes": self.changes.len(),
+                "moved": self.moved().count(),
+                "added": self.added().count(),
+                "removed": self.removed().count(),
+            },
+            "countries": countries,
+            "changes": changes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod diff_test {
+    use crate::{diff::*, extract::row};
+
+    #[test]
+    fn diff_csv_test() {
+        // 1.0.0.0/24 US, 1.0.1.0/24 CN, 1.0.2.0/23 CN, 1.0.4.0/22 AU
+        let old = country_ranges(&[
+            row(16777216, 16777471, "US"),
+            row(16777472, 16777727, "CN"),
+            row(16777728, 16778239, "CN"),
+            row(16778240, 16779263, "AU"),
+        ]).unwrap();
+        // 1.0.1.0/24 改为 JP，1.0.4.0/22 删除，新增 1.0.8.0/21 CN
+        let new = country_ranges(&[
+            row(16777216, 16777471, "US"),
+            row(16777472, 16777727, "JP"),
+            row(16777728, 16778239, "CN"),
+            row(16778240, 16779263, "-"),
+            row(16779264, 16781311, "CN"),
+        ]).unwrap();
+        let diff = diff(&old, &new);
+        assert_eq!(3, diff.changes.len());
+        assert_eq!(1, diff.moved().count());
+        assert_eq!(vec!["1.0.1.0/24".to_string()], range_cidrs(diff.changes[0].start, diff.changes[0].end));
+        assert_eq!((Some("CN"), Some("JP")), (diff.changes[0].from.as_deref(), diff.changes[0].to.as_deref()));
+        assert_eq!(CountryDiff { added: 2048, removed: 256 }, diff.countries["CN"]);
+        assert_eq!(CountryDiff { added: 0, removed: 1024 }, diff.countries["AU"]);
+        assert!(!diff.countries.contains_key("US"));
+        assert_eq!("3 ranges changed (1 moved, 1 added, 1 removed), +2304 -1280 addresses", diff.summary_line());
+
+        let text = diff.format_text(10);
+        assert!(text.contains("  1.0.8.0/21 - -> CN\n"), "{}", text);
+        assert!(text.contains("  1.0.4.0/22 AU -> -\n"), "{}", text);
+        let value = diff.to_json();
+        assert_eq!(3, value["summary"]["changes"]);
+        assert_eq!("CN", value["countries"][1]["country_code"]);
+        assert_eq!("+1792", value["countries"][1]["net"]);
+        assert_eq!("1.0.8.0", value["changes"][2]["start"]);
+        assert_eq!(serde_json::Value::Null, value["changes"][2]["from"]);
+        assert!(diff.format_text(0).contains("... and 1 more"));
+    }
+
+    #[test]
+    fn diff_ipv4_ipv6_test() {
+        let old = country_ranges(&[row(16777216, 16777471, "US")]).unwrap();
+        let new = country_ranges(&[
+            row(0xffff_0100_0000, 0xffff_0100_00ff, "US"),
+            row(42540528726795050063891204319802818560, 42540528806023212578155541913346768895, "JP"),
+        ]).unwrap();
+        let diff = diff(&old, &new);
+        assert_eq!(1, diff.changes.len());
+        assert_eq!(vec!["2001:200::/32".to_string()], range_cidrs(diff.changes[0].start, diff.changes[0].end));
+    }
+
+    #[test]
+    fn output_ranges_test() {
+        let clash = "  - GEOIP,LAN,DIRECT\n  - IP-CIDR,1.0.0.0/24,auto\n  - IP-CIDR,2001:200::/23,auto\n  - MATCH,DIRECT\n";
+        let list = output_ranges(clash, "*").unwrap();
+        assert_eq!(2, list.len());
+        assert_eq!((0xffff_0100_0000, 0xffff_0100_00ff), (list[0].0, list[0].1));
+        let proxifier = "\t\t<Rule enabled=\"true\">\n\t\t\t<Targets>1.0.0.0-1.0.0.255;1.0.4.0-1.0.7.255;</Targets>\n\t\t</Rule>\n";
+        let list2 = output_ranges(proxifier, "*").unwrap();
+        assert_eq!(2, list2.len());
+        let diff = diff(&list, &list2);
+        assert_eq!(2, diff.changes.len());
+        assert_eq!(1, diff.added().count());
+        assert_eq!(1, diff.removed().count());
+        assert!(output_ranges("  - IP-CIDR,1.0.0.0/33,auto", "*").is_err());
+        // 映射地址的 IPv6 写法同样按 IPv6 解析
+        let list3 = output_ranges("  - IP-CIDR,::ffff:1.0.0.0/120,auto", "*").unwrap();
+        assert_eq!((0xffff_0100_0000, 0xffff_0100_00ff), (list3[0].0, list3[0].1));
+        let routes = "route replace 1.0.0.0/24 via 192.168.1.1\nroute replace 2001:200::/23 via fe80::1\n";
+        assert_eq!(list, output_ranges(routes, "*").unwrap());
+        assert!(output_ranges("route replace 1.0.0.0/33 via 192.168.1.1", "*").is_err());
+    }
+}
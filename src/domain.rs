@@ -0,0 +1,145 @@
+use std::{collections::HashSet, fs, path::PathBuf};
+
+use crate::error::{self, Error};
+
+// 域名规则，放在 IP 规则之前
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DomainRule {
+    // 域名及其子域名
+    Suffix(String),
+    // 包含关键字的域名
+    Keyword(String),
+    // 完全相同的域名
+    Full(String),
+}
+
+impl DomainRule {
+    pub fn value(&self) -> &str {
+        match self {
+            DomainRule::Suffix(x) | DomainRule::Keyword(x) | DomainRule::Full(x) => x,
+        }
+    }
+
+    // clash 和 surge 中的规则类型
+    pub fn rule_type(&self) -> &'static str {
+        match self {
+            DomainRule::Suffix(_) => "DOMAIN-SUFFIX",
+            DomainRule::Keyword(_) => "DOMAIN-KEYWORD",
+            DomainRule::Full(_) => "DOMAIN",
+        }
+    }
+
+    // sing-box rule-set 中的字段
+    pub fn sing_box_key(&self) -> &'static str {
+        match self {
+            DomainRule::Suffix(_) => "domain_suffix",
+            DomainRule::Keyword(_) => "domain_keyword",
+            DomainRule::Full(_) => "domain",
+        }
+    }
+}
+
+fn valid_domain(domain: &str) -> bool {
+    !domain.is_empty() && domain.len() <= 253
+        && domain.split('.').all(|x| !x.is_empty() && x.len() <= 63 && x.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_'))
+}
+
+// 一行可以是：
+//   example.com                      域名及其子域名
+//   suffix:example.com               同上
+//   full:www.example.com             完全相同的域名
+//   keyword:google                   包含关键字的域名
+//   server=/example.com/114.114.114.114  dnsmasq-china-list 的格式，可以有多个域名
+// # 之后为注释
+fn parse_line(line: &str) -> Option<Vec<DomainRule>> {
+    if let Some(rest) = line.strip_prefix("server=/") {
+        // 最后一段是上游 DNS 服务器
+        let (domains, _) = rest.rsplit_once('/')?;
+        let domains = domains.split('/').map(|x| x.trim_start_matches('.').to_lowercase()).collect::<Vec<_>>();
+        if !domains.iter().all(|x| valid_domain(x)) {
+            return None;
+        }
+        return Some(domains.into_iter().map(DomainRule::Suffix).collect());
+    }
+    if let Some(keyword) = line.strip_prefix("keyword:") {
+        let keyword = keyword.trim().to_lowercase();
+        return if keyword.is_empty() || keyword.contains([',', ' ']) { None } else { Some(vec![DomainRule::Keyword(keyword)]) };
+    }
+    let (domain, full) = match line.strip_prefix("full:") {
+        Some(x) => (x, true),
+        None => (line.strip_prefix("suffix:").unwrap_or(line), false),
+    };
+    let domain = domain.trim().trim_start_matches('.').to_lowercase();
+    if !valid_domain(&domain) {
+        return None;
+    }
+    Some(vec![if full { DomainRule::Full(domain) } else { DomainRule::Suffix(domain) }])
+}
+
+// 重复的规则只保留第一条
+pub fn parse(content: &str, name: &str) -> error::Result<Vec<DomainRule>> {
+    let mut result = vec![];
+    let mut seen = HashSet::new();
+    for (i, line) in content.lines().enumerate() {
+        let text = line.split('#').next().unwrap_or_default().trim();
+        if text.is_empty() {
+            continue;
+        }
+        let rules = parse_line(text).ok_or_else(|| Error::Parse(format!("invalid domain at {}:{}: {}", name, i + 1, text)))?;
+        for rule in rules {
+            if seen.insert(rule.clone()) {
+                result.push(rule);
+            }
+        }
+    }
+    Ok(result)
+}
+
+pub fn read_files(files: &[PathBuf]) -> error::Result<Vec<DomainRule>> {
+    let mut result = vec![];
+    let mut seen = HashSet::new();
+    for path in files {
+        let content = fs::read_to_string(path).map_err(|e| Error::Config(format!("read domain file error {}, {}", path.display(), e)))?;
+        let name = path.file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+        for rule in parse(&content, &name)? {
+            if seen.insert(rule.clone()) {
+                result.push(rule);
+            }
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod domain_test {
+    use crate::{domain::*, error::Error};
+
+    #[test]
+    fn parse_test() {
+        let content = "# 常用
+example.com
+.Example.org  # 大小写和开头的点
+full:www.example.net
+keyword:google
+suffix:example.com
+server=/cn.example/114.114.114.114
+server=/a.example/b.example/223.5.5.5
+";
+        let rules = parse(content, "domains.txt").unwrap();
+        assert_eq!(vec![
+            DomainRule::Suffix("example.com".to_string()),
+            DomainRule::Suffix("example.org".to_string()),
+            DomainRule::Full("www.example.net".to_string()),
+            DomainRule::Keyword("google".to_string()),
+            DomainRule::Suffix("cn.example".to_string()),
+            DomainRule::Suffix("a.example".to_string()),
+            DomainRule::Suffix("b.example".to_string()),
+        ], rules);
+        for line in ["exa mple.com", "a..b", "keyword:", "server=/", "full:", "a,b.com"] {
+            match parse(line, "domains.txt") {
+                Err(Error::Parse(x)) => assert!(x.contains("domains.txt:1"), "{}", x),
+                x => panic!("unexpected result {:?} for {}", x, line),
+            }
+        }
+    }
+}
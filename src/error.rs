@@ -0,0 +1,95 @@
+use std::fmt;
+
+use crate::file_tool::DownloadError;
+
+// 按出错的阶段分类，每一类对应一个退出码，方便定时任务和告警区分处理
+#[derive(Debug)]
+pub enum Error {
+    // 参数或环境变量错误
+    Config(String),
+    // 网络错误或重试次数用完
+    Download(String),
+    // ZIP 无法打开或缺少需要的文件
+    Archive(String),
+    // CSV 或地址无法解析
+    Parse(String),
+    // 下载内容或生成结果没有通过校验
+    Validation(String),
+    // 输出文件写入失败
+    Output(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Config(_) => 2,
+            Error::Download(_) => 3,
+            Error::Archive(_) => 4,
+            Error::Parse(_) => 5,
+            Error::Validation(_) => 6,
+            Error::Output(_) => 7,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(x) => write!(f, "config error: {}", x),
+            Error::Download(x) => write!(f, "download error: {}", x),
+            Error::Archive(x) => write!(f, "archive error: {}", x),
+            Error::Parse(x) => write!(f, "parse error: {}", x),
+            Error::Validation(x) => write!(f, "validation error: {}", x),
+            Error::Output(x) => write!(f, "output error: {}", x),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// 服务器返回的错误页面和不完整的文件按下载失败处理，令牌、配额和校验和的问题按校验失败处理
+impl From<DownloadError> for Error {
+    fn from(e: DownloadError) -> Self {
+        match e {
+            DownloadError::NotZip(_) | DownloadError::TooSmall { .. } => Error::Download(e.to_string()),
+            DownloadError::InvalidToken(_) | DownloadError::QuotaExceeded(_) | DownloadError::ChecksumMismatch { .. } => Error::Validation(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod error_test {
+    use std::collections::HashSet;
+
+    use crate::{error::*, file_tool::DownloadError};
+
+    #[test]
+    fn exit_code_test() {
+        let list = [
+            Error::Config("a".to_string()),
+            Error::Download("a".to_string()),
+            Error::Archive("a".to_string()),
+            Error::Parse("a".to_string()),
+            Error::Validation("a".to_string()),
+            Error::Output("a".to_string()),
+        ];
+        let codes = list.iter().map(|x| x.exit_code()).collect::<HashSet<_>>();
+        assert_eq!(list.len(), codes.len());
+        assert!(!codes.contains(&0) && !codes.contains(&1));
+    }
+
+    #[test]
+    fn from_download_error_test() {
+        let e = Error::from(DownloadError::TooSmall { size: 4, min_size: 4096 });
+        assert_eq!(3, e.exit_code());
+        assert_eq!("download error: downloaded file is too small, 4 bytes < 4096 bytes", e.to_string());
+        assert_eq!(3, Error::from(DownloadError::NotZip("<html>".to_string())).exit_code());
+        let e = Error::from(DownloadError::InvalidToken("INVALID TOKEN.".to_string()));
+        assert_eq!(6, e.exit_code());
+        assert_eq!("validation error: invalid IP2LOCATION_TOKEN, server response: INVALID TOKEN.", e.to_string());
+        assert_eq!(6, Error::from(DownloadError::QuotaExceeded("LIMIT".to_string())).exit_code());
+        assert_eq!(6, Error::from(DownloadError::ChecksumMismatch { expected: "a".to_string(), actual: "b".to_string() }).exit_code());
+    }
+}
@@ -1,4 +1,6 @@
-use crate::{file_tool, ip_tool};
+use std::net::IpAddr;
+
+use crate::{domain::DomainRule, error::{self, Error}, file_tool, ip_tool};
 
 #[allow(dead_code)]
 #[derive(Debug)]
@@ -9,184 +11,474 @@ pub struct IpLocation {
     pub country_name: String,
 }
 
-impl file_tool::CsvTrait for IpLocation {
-    fn new(line: String) -> Option<Self> {
-        let line = line.replace("\"","");
-        let split = line.split(",").collect::<Vec<&str>>();
-        if split.len() != 4 {
-            return None;
+impl IpLocation {
+    // 起止地址对应的数字
+    pub fn range(&self) -> error::Result<(u128, u128)> {
+        let parse = |x: &str| x.parse::<u128>().map_err(|e| Error::Parse(format!("invalid ip number {}: {}", x, e)));
+        let start = parse(&self.ip_start)?;
+        let end = parse(&self.ip_end)?;
+        if start > end {
+            return Err(Error::Parse(format!("invalid ip range {}-{}", self.ip_start, self.ip_end)));
         }
+        Ok((start, end))
+    }
+}
+
+// 测试用的一行数据，国家名称与国家代码相同
+#[cfg(test)]
+pub fn row(ip_start: u128, ip_end: u128, country_code: &str) -> IpLocation {
+    IpLocation {
+        ip_start: ip_start.to_string(),
+        ip_end: ip_end.to_string(),
+        country_code: country_code.to_string(),
+        country_name: country_code.to_string(),
+    }
+}
+
+impl file_tool::CsvTrait for IpLocation {
+    fn from_fields(fields: Vec<String>) -> Option<Self> {
+        let [ip_start, ip_end, country_code, country_name]: [String; 4] = fields.try_into().ok()?;
         Some(Self {
-            ip_start: split.get(0)?.to_string(),
-            ip_end: split.get(1)?.to_string(),
-            country_code: split.get(2)?.to_string(),
-            country_name: split.get(3)?.to_string(),
+            ip_start,
+            ip_end,
+            country_code,
+            country_name,
         })
     }
 }
 
-pub fn collect(list: &[IpLocation], exclude_country_code: &str) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
-    let mut result = vec![];
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CoalescePolicy {
+    // 相邻两行之间存在地址空洞时是否仍然合并
+    pub bridge_gaps: bool,
+    // 是否跨过国家代码为 "-" 的行合并，只有两侧都是选中的行时才会跨过
+    pub bridge_unassigned: bool,
+}
 
-    let mut start_index = 0;
-    let mut end_index;
-    while start_index < list.len() {
-        let start: &IpLocation = list.get(start_index).unwrap();
-        if exclude_country_code == start.country_code || "-" == start.country_code {
-            start_index += 1;
-            continue;
-        }
-        end_index = start_index + 1;
-        while end_index < list.len() {
-            let end: &IpLocation = list.get(end_index).unwrap();
-            if end.ip_end.len() - start.ip_end.len() > 5 {
-                break;
-            }
-            if exclude_country_code == end.country_code || "-" == end.country_code {
-                break;
-            }
-            end_index += 1;
-        }
-        let from = ip_tool::u32_to_ipv4(start.ip_start.parse::<u32>()?);
-        let to = ip_tool::u32_to_ipv4(list.get(end_index - 1).unwrap().ip_end.parse::<u32>()?);
-        result.push((from, to));
+fn is_mergeable(current: (u128, u128), next_start: u128, policy: &CoalescePolicy) -> bool {
+    let (current_start, current_end) = current;
+    if next_start < current_start {
+        return false;
+    }
+    if next_start <= current_end || next_start - current_end == 1 {
+        return true;
+    }
+    policy.bridge_gaps
+}
 
-        start_index = end_index;
+pub fn coalesce(list: &[IpLocation], exclude_country_code: &str, policy: &CoalescePolicy) -> error::Result<Vec<(u128, u128)>> {
+    coalesce_by(list, |code| exclude_country_code != code, policy)
+}
+
+// selected 判断国家代码是否选中，"-" 行始终不选中
+pub fn coalesce_by(list: &[IpLocation], selected: impl Fn(&str) -> bool, policy: &CoalescePolicy) -> error::Result<Vec<(u128, u128)>> {
+    let mut result = vec![];
+    let mut current: Option<(u128, u128)> = None;
+    // 被跨过的 "-" 行的结束地址，后面紧跟选中的行时才并入 current
+    let mut bridged_end: Option<u128> = None;
+    for row in list {
+        let (start, end) = row.range()?;
+        let selected = "-" != row.country_code && selected(&row.country_code);
+        let bridgeable = policy.bridge_unassigned && "-" == row.country_code;
+        current = match current {
+            Some((current_start, current_end)) => {
+                let tail = (current_start, bridged_end.unwrap_or(current_end));
+                if selected && is_mergeable(tail, start, policy) {
+                    bridged_end = None;
+                    Some((current_start, end.max(current_end)))
+                } else if bridgeable && is_mergeable(tail, start, policy) {
+                    bridged_end = Some(end.max(tail.1));
+                    Some((current_start, current_end))
+                } else {
+                    result.push((current_start, current_end));
+                    bridged_end = None;
+                    if selected { Some((start, end)) } else { None }
+                }
+            },
+            None => if selected { Some((start, end)) } else { None },
+        };
+    }
+    if let Some(range) = current {
+        result.push(range);
     }
     Ok(result)
 }
 
-#[allow(dead_code)]
-pub fn collect_ipv6(list: &[IpLocation], exclude_country_code: &str) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+// 从 ranges 中去掉 remove 覆盖的地址，两者都不要求有序
+pub fn subtract(ranges: &[(u128, u128)], remove: &[(u128, u128)]) -> Vec<(u128, u128)> {
+    let mut remove = remove.to_vec();
+    remove.sort();
     let mut result = vec![];
-
-    let mut start_index = 0;
-    let mut end_index;
-    while start_index < list.len() {
-        let start: &IpLocation = list.get(start_index).unwrap();
-        if exclude_country_code == start.country_code || "-" == start.country_code {
-            start_index += 1;
-            continue;
-        }
-        end_index = start_index + 1;
-        while end_index < list.len() {
-            let end: &IpLocation = list.get(end_index).unwrap();
-            if end.ip_end.len() - start.ip_end.len() > 5 {
-                break;
+    for &(start, end) in ranges {
+        let mut start = Some(start);
+        for &(remove_start, remove_end) in &remove {
+            let current = match start {
+                None => break,
+                Some(x) => x,
+            };
+            if remove_end < current {
+                continue;
             }
-            if exclude_country_code == end.country_code || "-" == end.country_code {
+            if remove_start > end {
                 break;
             }
-            end_index += 1;
+            if remove_start > current {
+                result.push((current, remove_start - 1));
+            }
+            start = if remove_end >= end { None } else { Some(remove_end + 1) };
         }
-        let start_number = start.ip_start.parse::<u128>()?;
-        let end_number = list.get(end_index - 1).unwrap().ip_end.parse::<u128>()?;
-        let from;
-        let to;
-        if start_number >= 0xffff_0000_0000 && start_number <= 0xffff_ffff_ffff {
-            from = ip_tool::u32_to_ipv4((start_number & 0xffff_ffff).try_into().unwrap());
-            to = ip_tool::u32_to_ipv4((end_number & 0xffff_ffff).try_into().unwrap());
-        } else {
-            from = ip_tool::u128_to_ipv6(start_number);
-            to = ip_tool::u128_to_ipv6(end_number);
+        if let Some(start) = start {
+            result.push((start, end));
         }
-        result.push((from, to));
+    }
+    result.sort();
+    result
+}
+
+// 地址段覆盖的地址个数，超出 u128 时按最大值计
+pub fn size(ranges: &[(u128, u128)]) -> u128 {
+    ranges.iter().fold(0_u128, |sum, &(start, end)| sum.saturating_add((end - start).saturating_add(1)))
+}
+
+// 合并重叠和相邻的地址段，结果按起始地址排序
+pub fn union(ranges: &[(u128, u128)]) -> Vec<(u128, u128)> {
+    let mut ranges = ranges.to_vec();
+    ranges.sort();
+    let mut result: Vec<(u128, u128)> = vec![];
+    for (start, end) in ranges {
+        match result.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => result.push((start, end)),
+        }
+    }
+    result
+}
+
+// IPv6 数据集中包含映射到 ::ffff:0:0/96 的 IPv4 特殊用途地址
+pub fn special_purpose_ranges(categories: &[ip_tool::SpecialPurpose], ipv6: bool) -> Vec<(u128, u128)> {
+    if ipv6 {
+        ip_tool::special_purpose_ranges_ipv6(categories)
+    } else {
+        ip_tool::special_purpose_ranges_ipv4(categories).into_iter()
+            .map(|(start, end)| (start as u128, end as u128))
+            .collect()
+    }
+}
+
+pub fn exclude_special_purpose(ranges: &[(u128, u128)], categories: &[ip_tool::SpecialPurpose], ipv6: bool) -> Vec<(u128, u128)> {
+    subtract(ranges, &special_purpose_ranges(categories, ipv6))
+}
 
-        start_index = end_index;
+pub fn to_ipv4_list(ranges: &[(u128, u128)]) -> error::Result<Vec<(String, String)>> {
+    let mut result = vec![];
+    for &(start, end) in ranges {
+        let to_u32 = |x: u128| u32::try_from(x).map_err(|_| Error::Parse(format!("{} is not an ipv4 address", x)));
+        let from = ip_tool::u32_to_ipv4(to_u32(start)?);
+        let to = ip_tool::u32_to_ipv4(to_u32(end)?);
+        result.push((from, to));
     }
     Ok(result)
 }
 
-pub fn format_proxifier(list: &Vec<(String, String)>) -> String {
+pub fn to_ipv6_list(ranges: &[(u128, u128)]) -> Vec<(String, String)> {
+    to_ipv6_list_style(ranges, ip_tool::Ipv6Style::Canonical)
+}
+
+pub fn to_ipv6_list_style(ranges: &[(u128, u128)], style: ip_tool::Ipv6Style) -> Vec<(String, String)> {
+    let mapped = ip_tool::MAPPED..=ip_tool::MAPPED_END;
+    let mut result = vec![];
+    for &(start, end) in ranges {
+        if mapped.contains(&start) && mapped.contains(&end) {
+            result.push((ip_tool::u32_to_ipv4(start as u32), ip_tool::u32_to_ipv4(end as u32)));
+        } else {
+            result.push((ip_tool::format_ipv6(start, style), ip_tool::format_ipv6(end, style)));
+        }
+    }
+    result
+}
+
+#[allow(dead_code)]
+pub fn collect(list: &[IpLocation], exclude_country_code: &str, policy: &CoalescePolicy) -> error::Result<Vec<(String, String)>> {
+    to_ipv4_list(&coalesce(list, exclude_country_code, policy)?)
+}
+
+#[allow(dead_code)]
+pub fn collect_ipv6(list: &[IpLocation], exclude_country_code: &str, policy: &CoalescePolicy) -> error::Result<Vec<(String, String)>> {
+    Ok(to_ipv6_list(&coalesce(list, exclude_country_code, policy)?))
+}
+
+// direct 为 true 时规则的动作为直连，规则名为 IP-DIRECT-n
+pub fn format_proxifier_action(list: &[(String, String)], direct: bool) -> String {
+    let (action, name) = if direct { ("<Action type=\"Direct\" />", "DIRECT") } else { ("<Action type=\"Proxy\">100</Action>", "PROXY") };
+    let rule = |targets: &str, count: usize| format!("\t\t<Rule enabled=\"true\">
+\t\t\t{}
+\t\t\t<Targets>{}</Targets>
+\t\t\t<Name>IP-{}-{}</Name>
+\t\t</Rule>\n", action, targets, name, count);
     let mut result = String::default();
     let mut count = 1;
     let mut str = String::default();
     for (from, to) in list {
         if str.len() > 32000 {
-            result.push_str(&format!("\t\t<Rule enabled=\"true\">
-\t\t\t<Action type=\"Proxy\">100</Action>
-\t\t\t<Targets>{}</Targets>
-\t\t\t<Name>IP-PROXY-{}</Name>
-\t\t</Rule>\n", str, count));
+            result.push_str(&rule(&str, count));
             str = String::default();
             count += 1;
         }
         str.push_str(&format!("{}-{};", from, to));
     }
-    result.push_str(&format!("\t\t<Rule enabled=\"true\">
-\t\t\t<Action type=\"Proxy\">100</Action>
-\t\t\t<Targets>{}</Targets>
-\t\t\t<Name>IP-PROXY-{}</Name>
-\t\t</Rule>\n", str, count));
+    result.push_str(&rule(&str, count));
     result
 }
 
-pub fn format_clash(list: &Vec<(String, String)>) -> String {
+// 输出规则的选项：domains 放在 clash、surge、sing-box 的 IP 规则之前
+// no_resolve 时 IP 规则只匹配目标本来就是 IP 的连接，不为没有匹配域名规则的域名做 DNS 解析
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RuleOptions<'a> {
+    pub domains: &'a [DomainRule],
+    pub no_resolve: bool,
+    // IPv6 CIDR 的文本格式
+    pub ipv6_style: ip_tool::Ipv6Style,
+}
+
+impl RuleOptions<'_> {
+    fn suffix(&self) -> &'static str {
+        if self.no_resolve { ",no-resolve" } else { "" }
+    }
+}
+
+// to_ipv6_list 把 ::ffff:0:0/96 内的地址段输出为 IPv4，这里同样转为 IPv4 的 CIDR
+pub fn to_cidr_list(list: &[(String, String)], style: ip_tool::Ipv6Style) -> Vec<String> {
+    let restyle = |cidr: String| match cidr.split_once('/') {
+        Some((ip, prefix)) if style != ip_tool::Ipv6Style::Canonical => match ip_tool::ipv6_to_u128(ip) {
+            Some(number) => format!("{}/{}", ip_tool::format_ipv6(number, style), prefix),
+            None => cidr,
+        },
+        _ => cidr,
+    };
+    list.iter()
+        .filter_map(|(from, to)| if !to.contains(':') { ip_tool::ipv4_to_cidr(from, to) } else { ip_tool::ipv6_to_cidr(from, to).map(|x| x.into_iter().map(restyle).collect()) })
+        .flatten()
+        .collect()
+}
+
+// policy 为 clash 中的策略组名称，direct 为 true 时规则直连、其余流量走 policy，否则反过来
+pub fn format_clash_policy(list: &[(String, String)], policy: &str, direct: bool, options: &RuleOptions) -> String {
+    let (rule_policy, match_policy) = if direct { ("DIRECT", policy) } else { (policy, "DIRECT") };
     let mut result = String::default();
-    result.push_str("  - GEOIP,LAN,DIRECT\n");
-    for (from, to) in list {
-        match ip_tool::ipv4_to_cidr(&from, &to) {
-            None => {
-                continue
-            },
-            Some(cidrs) => {
-                for cidr in cidrs {
-                    result.push_str(&format!("  - IP-CIDR,{},auto\n", cidr));
-                }
-            }
-        };
+    for rule in options.domains {
+        result.push_str(&format!("  - {},{},{}\n", rule.rule_type(), rule.value(), rule_policy));
+    }
+    result.push_str(&format!("  - GEOIP,LAN,DIRECT{}\n", options.suffix()));
+    for cidr in to_cidr_list(list, options.ipv6_style) {
+        result.push_str(&format!("  - IP-CIDR,{},{}{}\n", cidr, rule_policy, options.suffix()));
     }
-    result.push_str("  - MATCH,DIRECT\n");
+    result.push_str(&format!("  - MATCH,{}\n", match_policy));
     result
 }
 
+// ip -batch 使用的路由表，每个 CIDR 经过 gateway
+pub fn format_routes(list: &[(String, String)], gateway: IpAddr, options: &RuleOptions) -> String {
+    to_cidr_list(list, options.ipv6_style).into_iter().map(|x| format!("route replace {} via {}\n", x, gateway)).collect()
+}
 
-#[allow(dead_code)]
-pub fn format_clash_ipv6(list: &Vec<(String, String)>) -> String {
+// surge 的 RULE-SET 文件，策略在引用规则集的地方指定
+pub fn format_surge(list: &[(String, String)], options: &RuleOptions) -> String {
     let mut result = String::default();
-    result.push_str("  - GEOIP,LAN,DIRECT\n");
-    for (from, to) in list {
-        if to.contains(".") {
-            match ip_tool::ipv4_to_cidr(&from, &to) {
-                None => {
-                    continue
-                },
-                Some(cidrs) => {
-                    for cidr in cidrs {
-                        result.push_str(&format!("  - IP-CIDR,{},auto\n", cidr));
-                    }
-                }
-            };
-        } else {
-            match ip_tool::ipv6_to_cidr(&from, &to) {
-                None => {
-                    continue
-                },
-                Some(cidrs) => {
-                    for cidr in cidrs {
-                        result.push_str(&format!("  - IP-CIDR,{},auto\n", cidr));
-                    }
-                }
-            };
-        }
+    for rule in options.domains {
+        result.push_str(&format!("{},{}\n", rule.rule_type(), rule.value()));
+    }
+    for cidr in to_cidr_list(list, options.ipv6_style) {
+        let kind = if cidr.contains(':') { "IP-CIDR6" } else { "IP-CIDR" };
+        result.push_str(&format!("{},{}{}\n", kind, cidr, options.suffix()));
     }
-    result.push_str("  - MATCH,DIRECT\n");
     result
 }
 
+// sing-box 的 source 格式 rule-set，域名规则单独一条放在前面，no-resolve 由 route 的配置决定
+pub fn format_sing_box(list: &[(String, String)], options: &RuleOptions) -> String {
+    let mut rules = vec![];
+    if !options.domains.is_empty() {
+        let mut rule = serde_json::Map::new();
+        for x in options.domains {
+            let entry = rule.entry(x.sing_box_key()).or_insert_with(|| serde_json::Value::Array(vec![]));
+            if let serde_json::Value::Array(list) = entry {
+                list.push(x.value().into());
+            }
+        }
+        rules.push(serde_json::Value::Object(rule));
+    }
+    rules.push(serde_json::json!({ "ip_cidr": to_cidr_list(list, options.ipv6_style) }));
+    let value = serde_json::json!({
+        "version": 1,
+        "rules": rules,
+    });
+    format!("{}\n", serde_json::to_string_pretty(&value).unwrap())
+}
+
+// 每行一个 CIDR，供路由器等导入
+pub fn format_list(list: &[(String, String)], options: &RuleOptions) -> String {
+    to_cidr_list(list, options.ipv6_style).into_iter().map(|x| x + "\n").collect()
+}
+
 #[cfg(test)]
 mod extract_test {
     use std::path::Path;
 
+    use proptest::prelude::*;
+
     use crate::{file_tool::*, extract::*};
 
+    #[test]
+    fn coalesce_adjacent_test() {
+        let list = vec![
+            row(0, 16777215, "-"),
+            row(16777216, 16777471, "US"),
+            row(16777472, 16778239, "CN"),
+            row(16778240, 16779263, "AU"),
+            row(16779264, 16781311, "CN"),
+            row(16781312, 16785407, "JP"),
+            row(16785408, 16793599, "JP"),
+        ];
+        assert_eq!(vec![
+            (16777216, 16777471),
+            (16778240, 16779263),
+            (16781312, 16793599),
+        ], coalesce(&list, "CN", &CoalescePolicy::default()).unwrap());
+    }
+
+    #[test]
+    fn coalesce_decimal_length_test() {
+        // 旧的实现按十进制字符串长度判断，这里结束地址的长度差超过 5 也必须合并
+        let list = vec![
+            row(0, 9, "US"),
+            row(10, 99999999, "US"),
+        ];
+        assert_eq!(vec![(0, 99999999)], coalesce(&list, "CN", &CoalescePolicy::default()).unwrap());
+        let list = vec![
+            row(0, 99999999, "US"),
+            row(100000000, 100000000, "US"),
+        ];
+        assert_eq!(vec![(0, 100000000)], coalesce(&list, "CN", &CoalescePolicy::default()).unwrap());
+    }
+
+    #[test]
+    fn coalesce_gap_test() {
+        let list = vec![
+            row(0, 9, "US"),
+            row(20, 29, "JP"),
+            row(30, 39, "CN"),
+            row(50, 59, "JP"),
+        ];
+        assert_eq!(vec![(0, 9), (20, 29), (50, 59)], coalesce(&list, "CN", &CoalescePolicy::default()).unwrap());
+        let policy = CoalescePolicy { bridge_gaps: true, bridge_unassigned: false };
+        assert_eq!(vec![(0, 29), (50, 59)], coalesce(&list, "CN", &policy).unwrap());
+    }
+
+    #[test]
+    fn coalesce_unassigned_test() {
+        let list = vec![
+            row(0, 9, "US"),
+            row(10, 19, "-"),
+            row(20, 29, "-"),
+            row(30, 39, "JP"),
+            row(40, 49, "-"),
+            row(50, 59, "CN"),
+        ];
+        assert_eq!(vec![(0, 9), (30, 39)], coalesce(&list, "CN", &CoalescePolicy::default()).unwrap());
+        let policy = CoalescePolicy { bridge_gaps: false, bridge_unassigned: true };
+        assert_eq!(vec![(0, 39)], coalesce(&list, "CN", &policy).unwrap());
+    }
+
+    #[test]
+    fn coalesce_invalid_test() {
+        assert!(coalesce(&[row(10, 9, "US")], "CN", &CoalescePolicy::default()).is_err());
+        assert!(coalesce(&[row(0, 9, "US"), IpLocation {
+            ip_start: "x".to_string(),
+            ip_end: "9".to_string(),
+            country_code: "US".to_string(),
+            country_name: "US".to_string(),
+        }], "CN", &CoalescePolicy::default()).is_err());
+    }
+
+    #[test]
+    fn subtract_test() {
+        assert_eq!(vec![(0, 9), (21, 29), (41, 100)], subtract(&[(0, 29), (35, 100)], &[(30, 40), (10, 20)]));
+        assert_eq!(Vec::<(u128, u128)>::new(), subtract(&[(10, 20)], &[(0, 100)]));
+        assert_eq!(vec![(10, 20)], subtract(&[(10, 20)], &[]));
+        assert_eq!(vec![(0, u128::MAX - 1)], subtract(&[(0, u128::MAX)], &[(u128::MAX, u128::MAX)]));
+    }
+
+    #[test]
+    fn exclude_special_purpose_test() {
+        let ranges = vec![(0x0a00_0000 - 256, 0x0b00_0000 + 255)];
+        assert_eq!(vec![(0x0a00_0000 - 256, 0x0a00_0000 - 1), (0x0b00_0000, 0x0b00_0000 + 255)],
+            exclude_special_purpose(&ranges, &[ip_tool::SpecialPurpose::Private], false));
+        let ranges = vec![(0xffff_0a00_0000 - 256, 0xffff_0b00_0000 + 255)];
+        assert_eq!(vec![(0xffff_0a00_0000 - 256, 0xffff_0a00_0000 - 1), (0xffff_0b00_0000, 0xffff_0b00_0000 + 255)],
+            exclude_special_purpose(&ranges, &ip_tool::SpecialPurpose::ALL, true));
+        assert_eq!(vec![("9.255.255.0".to_string(), "9.255.255.255".to_string())],
+            to_ipv4_list(&exclude_special_purpose(&[(0x09ff_ff00, 0x0aff_ffff)], &ip_tool::SpecialPurpose::ALL, false)).unwrap());
+    }
+
+    fn rows_strategy() -> impl Strategy<Value = Vec<IpLocation>> {
+        // (与上一行之间的空洞, 行长度, 国家)
+        prop::collection::vec((0u128..3, 1u128..5, prop::sample::select(vec!["CN", "US", "JP", "-"])), 0..40)
+            .prop_map(|rows| {
+                let mut next = 0;
+                rows.into_iter().map(|(gap, len, country_code)| {
+                    let start = next + gap;
+                    next = start + len;
+                    row(start, start + len - 1, country_code)
+                }).collect()
+            })
+    }
+
+    fn expand(ranges: &[(u128, u128)]) -> Vec<u128> {
+        ranges.iter().flat_map(|&(start, end)| start..=end).collect()
+    }
+
+    proptest! {
+        #[test]
+        fn coalesce_covers_selected_rows(list in rows_strategy()) {
+            let ranges = coalesce(&list, "CN", &CoalescePolicy::default()).unwrap();
+            let selected: Vec<u128> = list.iter()
+                .filter(|x| x.country_code != "CN" && x.country_code != "-")
+                .flat_map(|x| x.ip_start.parse::<u128>().unwrap()..=x.ip_end.parse::<u128>().unwrap())
+                .collect();
+            prop_assert_eq!(selected, expand(&ranges));
+            for pair in ranges.windows(2) {
+                // 输出的区间之间必须有间隔，否则说明漏合并了
+                prop_assert!(pair[0].1 + 1 < pair[1].0);
+            }
+        }
+
+        #[test]
+        fn coalesce_bridged_never_covers_excluded(list in rows_strategy(), bridge_gaps: bool, bridge_unassigned: bool) {
+            let policy = CoalescePolicy { bridge_gaps, bridge_unassigned };
+            let covered = expand(&coalesce(&list, "CN", &policy).unwrap());
+            for x in &list {
+                let start = x.ip_start.parse::<u128>().unwrap();
+                let end = x.ip_end.parse::<u128>().unwrap();
+                for ip in start..=end {
+                    let expected = match x.country_code.as_str() {
+                        "CN" => false,
+                        "-" if bridge_unassigned => continue,
+                        "-" => false,
+                        _ => true,
+                    };
+                    prop_assert_eq!(expected, covered.binary_search(&ip).is_ok());
+                }
+            }
+        }
+    }
+
     #[test]
     fn collect_test() {
         let file_path = Path::new(r"C:\data\ip-extract\IP2LOCATION-LITE-DB1.IPV6.CSV");
-        let list: Vec<IpLocation> = read_csv::<IpLocation>(&file_path).unwrap_or_else(|e| {
+        let list: Vec<IpLocation> = read_csv::<IpLocation>(file_path, false).unwrap_or_else(|e| {
             panic!("read csv file error {}", e)
         });
-        let str_list = collect(&list, "CN").unwrap_or_else(|e| {
+        let str_list = collect(&list, "CN", &CoalescePolicy::default()).unwrap_or_else(|e| {
             panic!("collect ip error {}", e)
         });
         assert_eq!(true, str_list.len() == 7);
@@ -195,13 +487,13 @@ mod extract_test {
     #[test]
     fn format_proxifier_test() {
         let file_path = Path::new(r"C:\data\ip-extract\IP2LOCATION-LITE-DB1.IPV6.CSV");
-        let list: Vec<IpLocation> = read_csv::<IpLocation>(&file_path).unwrap_or_else(|e| {
+        let list: Vec<IpLocation> = read_csv::<IpLocation>(file_path, false).unwrap_or_else(|e| {
             panic!("read csv file error {}", e)
         });
-        let str_list = collect(&list, "CN").unwrap_or_else(|e| {
+        let str_list = collect(&list, "CN", &CoalescePolicy::default()).unwrap_or_else(|e| {
             panic!("collect ip error {}", e)
         });
-        let format_list = format_proxifier(&str_list);
+        let format_list = format_proxifier_action(&str_list, false);
         let output_dir = file_path.parent().unwrap().join("proxifier.txt");
         write_file(&output_dir, format_list).unwrap_or_else(|e| {
             panic!("write file error {}", e)
@@ -212,13 +504,13 @@ mod extract_test {
     #[test]
     fn format_clash_test() {
         let file_path = Path::new("C:/data/ip-extract/IP2LOCATION-LITE-DB1.CSV");
-        let list: Vec<IpLocation> = read_csv::<IpLocation>(&file_path).unwrap_or_else(|e| {
+        let list: Vec<IpLocation> = read_csv::<IpLocation>(file_path, false).unwrap_or_else(|e| {
             panic!("read csv file error {}", e)
         });
-        let str_list = collect(&list, "CN").unwrap_or_else(|e| {
+        let str_list = collect(&list, "CN", &CoalescePolicy::default()).unwrap_or_else(|e| {
             panic!("collect ip error {}", e)
         });
-        let format_list2 = format_clash(&str_list);
+        let format_list2 = format_clash_policy(&str_list, "auto", false, &RuleOptions::default());
         println!("format clash successed!");
     
         let output_dir = file_path.parent().unwrap().join("clash.txt");
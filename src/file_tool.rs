@@ -1,111 +1,1066 @@
-use std::{fs, fs::File};
+use std::{collections::HashMap, fs, fs::File};
 use std::io::{self, Read, Write, BufReader, BufRead};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-#[tokio::main]
-pub async fn download_file(url: &str, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    let body = reqwest::get(url).await?
-        .bytes().await?;
-    let dir = path.parent();
-    if None != dir {
-        let dir = dir.unwrap();
-        fs::create_dir_all(dir).unwrap_or_else(|e| {
-            panic!("Could not create file directory: {}, {:?}", dir.display(), e)
-        });
+use flate2::read::GzDecoder;
+use reqwest::{header, StatusCode};
+use sha2::{Digest, Sha256};
+
+use crate::error::{self, Error};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DownloadStatus {
+    Downloaded,
+    NotModified,
+}
+
+pub struct DownloadOptions {
+    // 失败后的重试次数，以及第一次重试前的等待时间，之后每次翻倍
+    pub retries: u32,
+    pub retry_delay: Duration,
+    pub connect_timeout: Duration,
+    // 两次收到数据之间的最长间隔
+    pub read_timeout: Duration,
+    pub min_size: u64,
+    pub sha256: Option<String>,
+    // http://、https://、socks5:// 或 socks5h:// 代理
+    pub proxy: Option<String>,
+    // 额外信任的根证书，PEM 格式，一个文件中可以有多个证书
+    pub ca_bundles: Vec<PathBuf>,
+    pub user_agent: String,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        DownloadOptions {
+            retries: 3,
+            retry_delay: Duration::from_secs(5),
+            connect_timeout: Duration::from_secs(30),
+            read_timeout: Duration::from_secs(60),
+            min_size: 4096,
+            sha256: None,
+            proxy: None,
+            ca_bundles: vec![],
+            user_agent: format!("ip-extract/{}", env!("CARGO_PKG_VERSION")),
+        }
+    }
+}
+
+fn pem_certificates(pem: &str) -> Vec<String> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    const END: &str = "-----END CERTIFICATE-----";
+    let mut list = vec![];
+    let mut rest = pem;
+    while let Some(begin) = rest.find(BEGIN) {
+        let end = match rest[begin..].find(END) {
+            None => break,
+            Some(x) => begin + x + END.len(),
+        };
+        list.push(rest[begin..end].to_string());
+        rest = &rest[end..];
+    }
+    list
+}
+
+pub fn build_client(options: &DownloadOptions) -> Result<reqwest::Client, Box<dyn std::error::Error>> {
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(options.connect_timeout)
+        .user_agent(&options.user_agent);
+    if let Some(proxy) = &options.proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy).map_err(|e| format!("invalid proxy {}: {}", proxy, e))?);
+    }
+    for path in &options.ca_bundles {
+        let pem = fs::read_to_string(path).map_err(|e| format!("read ca bundle {} error {}", path.display(), e))?;
+        let certificates = pem_certificates(&pem);
+        if certificates.is_empty() {
+            return Err(format!("no certificate found in ca bundle {}", path.display()).into());
+        }
+        for certificate in certificates {
+            let certificate = reqwest::Certificate::from_pem(certificate.as_bytes())
+                .map_err(|e| format!("invalid certificate in ca bundle {}: {}", path.display(), e))?;
+            builder = builder.add_root_certificate(certificate);
+        }
     }
-    let mut file = File::create(path).unwrap_or_else(|e| {
-        panic!("Could not create file: {:?}", e);
-    });
-    let content = body.bytes();
-    let data: Result<Vec<_>, _> = content.collect();
-    file.write_all(&data.unwrap())?;
+    Ok(builder.build()?)
+}
+
+// 下载内容校验失败的原因，ip2location 在 Token 错误或超出下载次数时会返回 200 和一段文字
+#[derive(Debug, PartialEq, Eq)]
+pub enum DownloadError {
+    InvalidToken(String),
+    QuotaExceeded(String),
+    NotZip(String),
+    TooSmall { size: u64, min_size: u64 },
+    ChecksumMismatch { expected: String, actual: String },
+}
 
+impl std::fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloadError::InvalidToken(x) => write!(f, "invalid IP2LOCATION_TOKEN, server response: {}", x),
+            DownloadError::QuotaExceeded(x) => write!(f, "download quota exceeded, server response: {}", x),
+            DownloadError::NotZip(x) => write!(f, "downloaded file is not a zip archive, content: {}", x),
+            DownloadError::TooSmall { size, min_size } => write!(f, "downloaded file is too small, {} bytes < {} bytes", size, min_size),
+            DownloadError::ChecksumMismatch { expected, actual } => write!(f, "sha256 mismatch, expected {} but got {}", expected, actual),
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+const INVALID_TOKEN_MESSAGES: [&str; 4] = ["invalid token", "no permission", "unauthorized", "not authorized"];
+const QUOTA_EXCEEDED_MESSAGES: [&str; 4] = ["times per", "limit", "quota", "exceeded"];
+
+pub fn validate_download(path: &Path, options: &DownloadOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = File::open(path)?;
+    let mut head = vec![];
+    (&mut file).take(512).read_to_end(&mut head)?;
+    if !head.starts_with(b"PK\x03\x04") {
+        let text = String::from_utf8_lossy(&head);
+        let message = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        let lowercase = message.to_lowercase();
+        if INVALID_TOKEN_MESSAGES.iter().any(|x| lowercase.contains(x)) {
+            return Err(DownloadError::InvalidToken(message).into());
+        }
+        if QUOTA_EXCEEDED_MESSAGES.iter().any(|x| lowercase.contains(x)) {
+            return Err(DownloadError::QuotaExceeded(message).into());
+        }
+        return Err(DownloadError::NotZip(message.chars().take(200).collect()).into());
+    }
+    let size = file.metadata()?.len();
+    if size < options.min_size {
+        return Err(DownloadError::TooSmall { size, min_size: options.min_size }.into());
+    }
+    if let Some(expected) = &options.sha256 {
+        let mut file = File::open(path)?;
+        let mut hasher = Sha256::new();
+        io::copy(&mut file, &mut hasher)?;
+        let actual = format!("{:x}", hasher.finalize());
+        if !actual.eq_ignore_ascii_case(expected.trim()) {
+            return Err(DownloadError::ChecksumMismatch { expected: expected.trim().to_lowercase(), actual }.into());
+        }
+    }
     Ok(())
 }
 
-pub fn unzip(source: &Path, target: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    let zip_file = File::open(source).unwrap_or_else(|e| {
-        panic!("open file error {}", e)
-    });
-    if !target.exists() {
-        fs::create_dir_all(target).unwrap_or_else(|e| {
-            panic!("Could not create target directory: {}, {:?}", target.display(), e)
-        });
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut new_path = path.as_os_str().to_owned();
+    new_path.push(suffix);
+    PathBuf::from(new_path)
+}
+
+// 与下载文件放在一起的缓存信息，保存上次响应的 ETag 和 Last-Modified
+fn meta_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".meta")
+}
+
+// 上一次下载保存的 ETag 和 Last-Modified，没有下载过时为空
+pub fn download_validators(path: &Path) -> String {
+    fs::read_to_string(meta_path(path)).unwrap_or_default()
+}
+
+fn read_meta(path: &Path) -> HashMap<String, String> {
+    fs::read_to_string(path).unwrap_or_default().lines()
+        .filter_map(|x| x.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+fn response_meta(response: &reqwest::Response) -> String {
+    let mut meta = String::new();
+    for (key, name) in [("etag", header::ETAG), ("last-modified", header::LAST_MODIFIED)] {
+        if let Some(x) = response.headers().get(name).and_then(|x| x.to_str().ok()) {
+            meta.push_str(&format!("{}={}\n", key, x));
+        }
     }
-    if target.is_file() {
-        panic!("Could not create target directory: {}, Because a file with the same name exists", target.display())
+    meta
+}
+
+// 4xx 说明请求本身有问题，重试也没用，408 和 429 除外
+fn is_retryable(error: &(dyn std::error::Error + 'static)) -> bool {
+    if error.is::<DownloadError>() {
+        return false;
+    }
+    match error.downcast_ref::<reqwest::Error>().and_then(|x| x.status()) {
+        Some(status) => !status.is_client_error() || status == StatusCode::REQUEST_TIMEOUT || status == StatusCode::TOO_MANY_REQUESTS,
+        None => error.downcast_ref::<io::Error>().map(|x| x.kind() != io::ErrorKind::PermissionDenied).unwrap_or(true),
+    }
+}
+
+// 重试等待时间的上限
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(600);
+
+// 第 attempt 次重试前的等待时间，每次翻倍，溢出或超过上限时取上限
+fn retry_delay(delay: Duration, attempt: u32) -> Duration {
+    2_u32.checked_pow(attempt).and_then(|x| delay.checked_mul(x)).map_or(MAX_RETRY_DELAY, |x| x.min(MAX_RETRY_DELAY))
+}
+
+// 先下载到 .part 文件，中断后用 Range 请求续传，下载完整后才重命名为目标文件
+#[tokio::main]
+pub async fn download_file(url: &str, path: &Path, options: &DownloadOptions) -> error::Result<DownloadStatus> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| Error::Download(format!("Could not create file directory: {}, {}", dir.display(), e)))?;
+    }
+    let client = build_client(options).map_err(|e| Error::Config(e.to_string()))?;
+    let mut attempt = 0;
+    loop {
+        match download_once(&client, url, path, options).await {
+            Ok(status) => return Ok(status),
+            Err(e) if attempt < options.retries && is_retryable(e.as_ref()) => {
+                let delay = retry_delay(options.retry_delay, attempt);
+                attempt += 1;
+                eprintln!("download file error {}, retry {}/{} in {:?}", e, attempt, options.retries, delay);
+                tokio::time::sleep(delay).await;
+            },
+            Err(e) => return Err(match e.downcast::<DownloadError>() {
+                Ok(e) => Error::from(*e),
+                Err(e) => Error::Download(e.to_string()),
+            }),
+        }
+    }
+}
+
+async fn download_once(client: &reqwest::Client, url: &str, path: &Path, options: &DownloadOptions) -> Result<DownloadStatus, Box<dyn std::error::Error>> {
+    let meta_path = meta_path(path);
+    let part_path = with_suffix(path, ".part");
+    let part_meta_path = with_suffix(&part_path, ".meta");
+    let mut request = client.get(url);
+
+    let part_meta = read_meta(&part_meta_path);
+    let part_len = fs::metadata(&part_path).map(|x| x.len()).unwrap_or(0);
+    // 只有知道未完成文件的版本时才续传，If-Range 保证服务端文件变了会重新返回完整内容
+    let validator = part_meta.get("etag").or(part_meta.get("last-modified"));
+    let resume_from = match validator {
+        Some(validator) if part_len > 0 => {
+            request = request
+                .header(header::RANGE, format!("bytes={}-", part_len))
+                .header(header::IF_RANGE, validator);
+            part_len
+        },
+        _ => 0,
+    };
+    if resume_from == 0 && path.exists() {
+        let meta = read_meta(&meta_path);
+        if let Some(x) = meta.get("etag") {
+            request = request.header(header::IF_NONE_MATCH, x);
+        }
+        if let Some(x) = meta.get("last-modified") {
+            request = request.header(header::IF_MODIFIED_SINCE, x);
+        }
+    }
+
+    let mut response = tokio::time::timeout(options.read_timeout, request.send()).await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "request timed out"))??;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(DownloadStatus::NotModified);
+    }
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        let _ = fs::remove_file(&part_path);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "resume range not satisfiable, restart download").into());
     }
-    let mut zip = zip::ZipArchive::new(zip_file)?;
+    let response_status = response.error_for_status_ref().map(|x| x.status())?;
+    let meta = response_meta(&response);
+    let resumed = response_status == StatusCode::PARTIAL_CONTENT;
+    let mut file = if resumed {
+        println!("resume download from {} bytes", resume_from);
+        fs::OpenOptions::new().append(true).open(&part_path)?
+    } else {
+        fs::write(&part_meta_path, &meta)?;
+        File::create(&part_path)?
+    };
+    let mut downloaded = if resumed { resume_from } else { 0 };
+    let total = response.content_length().map(|x| x + downloaded);
+
+    let mut reported = 0;
+    loop {
+        let chunk = tokio::time::timeout(options.read_timeout, response.chunk()).await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "read timed out"))??;
+        let chunk = match chunk {
+            None => break,
+            Some(x) => x,
+        };
+        file.write_all(&chunk)?;
+        downloaded += chunk.len() as u64;
+        if let Some(total) = total {
+            let percent = downloaded * 100 / total.max(1);
+            if percent >= reported + 10 || downloaded == total {
+                reported = percent - percent % 10;
+                println!("download progress {}% ({}/{} bytes)", percent, downloaded, total);
+            }
+        }
+    }
+    file.sync_all()?;
+    drop(file);
+    if let Some(total) = total {
+        if downloaded != total {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("incomplete download {}/{} bytes", downloaded, total)).into());
+        }
+    }
+
+    if let Err(e) = validate_download(&part_path, options) {
+        let _ = fs::remove_file(&part_path);
+        let _ = fs::remove_file(&part_meta_path);
+        return Err(e);
+    }
+    fs::rename(&part_path, path)?;
+    let _ = fs::remove_file(&part_meta_path);
+    if meta.is_empty() {
+        let _ = fs::remove_file(&meta_path);
+    } else {
+        fs::write(&meta_path, meta)?;
+    }
+    Ok(DownloadStatus::Downloaded)
+}
+
+// 查找文件名为 member 的条目，用 enclosed_name 过滤掉 ../ 或绝对路径这类会写到目录之外的条目
+fn find_member<R: Read + io::Seek>(source: &Path, zip: &mut zip::ZipArchive<R>, member: &str) -> error::Result<usize> {
     for i in 0..zip.len() {
-        let mut file = zip.by_index(i).unwrap();
+        let file = zip.by_index(i).map_err(|e| Error::Archive(format!("{}: {}", source.display(), e)))?;
         if file.is_dir() {
-            println!("file utf8 path {:?}", file.name_raw());
-            let target_dir = target.join(Path::new(&file.name()));
-            fs::create_dir_all(&target_dir).unwrap_or_else(|e| {
-                panic!("Could not create target directory: {}, {:?}", &target_dir.display(), e)
-            });
-        } else {
-            let file_path = target.join(Path::new(file.name()));
-            let mut target_file = File::create(&file_path).unwrap_or_else(|e| {
-                panic!("Could not create file: {}, {:?}", &file_path.display(), e);
-            });
-            io::copy(&mut file, &mut target_file).unwrap_or_else(|e| {
-                panic!("Could not copy file: {}, {:?}", &file_path.display(), e);
-            });
+            continue;
+        }
+        let name = match file.enclosed_name() {
+            None => {
+                eprintln!("skip unsafe zip entry {:?}", file.name());
+                continue;
+            },
+            Some(x) => x.to_path_buf(),
+        };
+        if name.file_name().map(|x| x.to_string_lossy().eq_ignore_ascii_case(member)) == Some(true) {
+            return Ok(i);
         }
     }
-    Ok(())
+    Err(Error::Archive(format!("{} not found in zip {}", member, source.display())))
+}
+
+fn open_zip(source: &Path) -> error::Result<zip::ZipArchive<BufReader<File>>> {
+    let zip_file = File::open(source).map_err(|e| Error::Archive(format!("open file error {}, {}", source.display(), e)))?;
+    zip::ZipArchive::new(BufReader::new(zip_file)).map_err(|e| Error::Archive(format!("{}: {}", source.display(), e)))
+}
+
+// 只解压文件名为 member 的文件到 target 目录下，返回解压后的路径
+pub fn unzip(source: &Path, target: &Path, member: &str) -> error::Result<PathBuf> {
+    let mut zip = open_zip(source)?;
+    if target.is_file() {
+        return Err(Error::Output(format!("Could not create target directory: {}, Because a file with the same name exists", target.display())));
+    }
+    fs::create_dir_all(target).map_err(|e| Error::Output(format!("Could not create target directory: {}, {}", target.display(), e)))?;
+    let index = find_member(source, &mut zip, member)?;
+    let mut file = zip.by_index(index).map_err(|e| Error::Archive(format!("{}: {}", source.display(), e)))?;
+    let file_path = target.join(member);
+    let temp_path = target.join(format!(".{}.tmp", member));
+    let mut target_file = File::create(&temp_path).map_err(|e| Error::Output(format!("Could not create file: {}, {}", temp_path.display(), e)))?;
+    if let Err(e) = io::copy(&mut file, &mut target_file) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(Error::Archive(format!("Could not copy file: {}, {}", file_path.display(), e)));
+    }
+    drop(target_file);
+    fs::rename(&temp_path, &file_path).map_err(|e| Error::Output(format!("Could not rename file: {}, {}", file_path.display(), e)))?;
+    Ok(file_path)
 }
 
 pub trait CsvTrait {
-    fn new(line: String) -> Option<Self> where Self: Sized;
+    fn from_fields(fields: Vec<String>) -> Option<Self> where Self: Sized;
+}
+
+// 一条 CSV 记录的解析结果：Open 为引号内的字段还没有结束，需要拼接下一行
+enum CsvRecord {
+    Complete(Vec<String>),
+    Open,
+    Malformed,
+}
+
+fn parse_csv_record(text: &str) -> CsvRecord {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut chars = text.chars().peekable();
+    loop {
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    None => return CsvRecord::Open,
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    },
+                    Some('"') => break,
+                    Some(c) => field.push(c),
+                }
+            }
+            match chars.next() {
+                None => break,
+                Some(',') => fields.push(std::mem::take(&mut field)),
+                Some(_) => return CsvRecord::Malformed,
+            }
+        } else {
+            match chars.next() {
+                None => break,
+                Some(',') => fields.push(std::mem::take(&mut field)),
+                Some('"') => return CsvRecord::Malformed,
+                Some(c) => field.push(c),
+            }
+        }
+    }
+    fields.push(field);
+    CsvRecord::Complete(fields)
+}
+
+// 按 RFC 4180 拆分一行 CSV：字段可以用双引号包裹，引号内可以有逗号和换行，"" 表示一个双引号
+// 引号未闭合或闭合引号后还有其他字符时返回 None
+#[allow(dead_code)]
+pub fn split_csv_line(line: &str) -> Option<Vec<String>> {
+    match parse_csv_record(line) {
+        CsvRecord::Complete(fields) => Some(fields),
+        CsvRecord::Open | CsvRecord::Malformed => None,
+    }
+}
+
+pub fn read_csv<T: CsvTrait>(path: &Path, strict: bool) -> error::Result<Vec<T>> {
+    let file: File = File::open(path).map_err(|e| Error::Parse(format!("open file error {}, {}", path.display(), e)))?;
+    read_csv_from(BufReader::new(file), strict)
 }
 
-pub fn read_csv<T: CsvTrait>(path: &Path) -> Result<Vec<T>, Box<dyn std::error::Error>> {
-    let file: File = File::open(path)?;
+// 引号内有换行的记录最多跨的行数，超过后按引号未闭合处理
+const MAX_RECORD_LINES: usize = 16;
+
+// 无法解析的行会被跳过并统计，strict 为 true 时遇到第一行就返回错误
+// 记录是否跨行由上一行结束时引号是否闭合决定；拼接后仍无法解析时只丢弃第一行，后面的行重新解析
+pub fn read_csv_from<T: CsvTrait, R: BufRead>(reader: R, strict: bool) -> error::Result<Vec<T>> {
     let mut list = vec![];
-    let lines = BufReader::new(file).lines();
-    for line in lines {
-        match T::new(line?) {
-            None => continue,
-            Some(x) => list.push(x),
+    let mut rejected = vec![];
+    let mut lines = reader.lines().enumerate();
+    // 需要重新解析的行
+    let mut pending = std::collections::VecDeque::new();
+    let mut record: Vec<(usize, String)> = vec![];
+    let mut eof = false;
+    loop {
+        let next = match pending.pop_front() {
+            Some(x) => Some(x),
+            None if eof => None,
+            None => match lines.next() {
+                None => {
+                    eof = true;
+                    None
+                },
+                Some((index, line)) => {
+                    let line = line.map_err(|e| Error::Parse(format!("read line {} error {}", index + 1, e)))?;
+                    Some((index + 1, line.strip_suffix('\r').map(|x| x.to_string()).unwrap_or(line)))
+                },
+            },
+        };
+        let done = next.is_none();
+        if let Some(x) = next {
+            record.push(x);
+        }
+        if record.is_empty() {
+            break;
         }
+        let text = record.iter().map(|x| x.1.as_str()).collect::<Vec<_>>().join("\n");
+        let record_line = record[0].0;
+        match parse_csv_record(&text) {
+            CsvRecord::Open if !done && record.len() < MAX_RECORD_LINES => continue,
+            CsvRecord::Complete(fields) => {
+                record.clear();
+                if text.trim().is_empty() {
+                    continue;
+                }
+                match T::from_fields(fields) {
+                    Some(x) => list.push(x),
+                    None if strict => return Err(Error::Parse(format!("malformed csv row at line {}: {}", record_line, text))),
+                    None => rejected.push(record_line),
+                }
+            },
+            result => {
+                if strict {
+                    return Err(Error::Parse(match result {
+                        CsvRecord::Open => format!("unterminated quoted field at line {}", record_line),
+                        _ => format!("malformed csv row at line {}: {}", record_line, text),
+                    }));
+                }
+                rejected.push(record_line);
+                let rest = record.split_off(1);
+                record.clear();
+                rest.into_iter().rev().for_each(|x| pending.push_front(x));
+            },
+        }
+    }
+    if !rejected.is_empty() {
+        let sample = rejected.iter().take(5).map(|x| x.to_string()).collect::<Vec<_>>().join(", ");
+        eprintln!("rejected {} malformed csv rows, at line {}{}", rejected.len(), sample, if rejected.len() > 5 { ", ..." } else { "" });
     }
     Ok(list)
 }
 
-pub fn write_file(path: &Path, str: String)-> Result<(), Box<dyn std::error::Error>> {
-    let parent = path.parent().unwrap();
-    if !parent.exists() {
-        fs::create_dir_all(&parent).unwrap_or_else(|e| {
-            panic!("Could not create target directory: {}, {:?}", parent.display(), e)
-        });
+// 直接从 ZIP 中读取 CSV，不解压到磁盘
+pub fn read_csv_zip<T: CsvTrait>(source: &Path, member: &str, strict: bool) -> error::Result<Vec<T>> {
+    let mut zip = open_zip(source)?;
+    let index = find_member(source, &mut zip, member)?;
+    let file = zip.by_index(index).map_err(|e| Error::Archive(format!("{}: {}", source.display(), e)))?;
+    read_csv_from(BufReader::new(file), strict)
+}
+
+// 按扩展名读取 CSV：- 表示标准输入，.gz 为 gzip 压缩的 CSV，.zip 中读取名为 member 的文件
+pub fn read_csv_input<T: CsvTrait>(input: &str, member: &str, strict: bool) -> error::Result<Vec<T>> {
+    let lowercase = input.to_lowercase();
+    if input == "-" {
+        read_csv_from(io::stdin().lock(), strict)
+    } else if lowercase.ends_with(".gz") {
+        let file = File::open(input).map_err(|e| Error::Parse(format!("open file error {}, {}", input, e)))?;
+        read_csv_from(BufReader::new(GzDecoder::new(file)), strict)
+    } else if lowercase.ends_with(".zip") {
+        read_csv_zip(Path::new(input), member, strict)
+    } else {
+        read_csv(Path::new(input), strict)
+    }
+}
+
+fn temp_path(path: &Path) -> error::Result<PathBuf> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(file_name)) => Ok(parent.join(format!(".{}.tmp", file_name.to_string_lossy()))),
+        _ => Err(Error::Output(format!("invalid file path: {}", path.display()))),
+    }
+}
+
+// 写入同目录下的临时文件并 fsync，返回临时文件路径
+fn write_temp(path: &Path, content: &[u8]) -> error::Result<PathBuf> {
+    let output_error = |e: io::Error| Error::Output(format!("write file error {}, {}", path.display(), e));
+    let temp_path = temp_path(path)?;
+    if let Some(parent) = path.parent().filter(|x| !x.as_os_str().is_empty() && !x.exists()) {
+        fs::create_dir_all(parent).map_err(output_error)?;
+    }
+    let mut file: File = File::create(&temp_path).map_err(output_error)?;
+    if let Err(e) = file.write_all(content).and_then(|_| file.sync_all()) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(output_error(e));
+    }
+    Ok(temp_path)
+}
+
+// 重命名之后同步目录，保证断电后目录项也是新的
+fn sync_dir(dir: &Path) {
+    if let Ok(dir) = File::open(if dir.as_os_str().is_empty() { Path::new(".") } else { dir }) {
+        let _ = dir.sync_all();
+    }
+}
+
+// 先写到同目录下的临时文件再重命名，读取方不会看到写了一半的文件
+pub fn write_file(path: &Path, str: String)-> error::Result<()> {
+    write_files(&[(path.to_path_buf(), str)])
+}
+
+// 所有文件都写好临时文件后才依次重命名，任何一个写入失败都不会替换已有的文件
+pub fn write_files<T: AsRef<[u8]>>(files: &[(PathBuf, T)]) -> error::Result<()> {
+    let mut temp_paths = vec![];
+    for (path, content) in files {
+        match write_temp(path, content.as_ref()) {
+            Ok(x) => temp_paths.push(x),
+            Err(e) => {
+                temp_paths.iter().for_each(|x| { let _ = fs::remove_file(x); });
+                return Err(e);
+            },
+        }
+    }
+    for ((path, _), temp_path) in files.iter().zip(temp_paths) {
+        fs::rename(&temp_path, path).map_err(|e| Error::Output(format!("rename file error {}, {}", path.display(), e)))?;
+        if let Some(parent) = path.parent() {
+            sync_dir(parent);
+        }
     }
-    let mut file: File = File::create(path)?;
-    file.write(str.as_bytes())?;
-    file.flush()?;
     Ok(())
 }
 
+// 输出文件的历史版本，每次替换前整组保存到输出目录下的 .generations/<时间>/ 中
+pub const GENERATIONS_DIR: &str = ".generations";
+// 版本中记录替换它的那一组输出的文件名，每行一个
+const MANIFEST_NAME: &str = ".manifest";
+
+// 按时间从旧到新排列
+pub fn list_generations(dir: &Path) -> error::Result<Vec<PathBuf>> {
+    let generations_dir = dir.join(GENERATIONS_DIR);
+    if !generations_dir.exists() {
+        return Ok(vec![]);
+    }
+    let entries = fs::read_dir(&generations_dir).map_err(|e| Error::Output(format!("read dir error {}, {}", generations_dir.display(), e)))?;
+    let mut list = entries.filter_map(|x| x.ok())
+        .map(|x| x.path())
+        .filter(|x| x.is_dir() && !x.file_name().map(|x| x.to_string_lossy().starts_with('.')).unwrap_or(true))
+        .collect::<Vec<_>>();
+    list.sort();
+    Ok(list)
+}
+
+// 把 dir 下现有的 names 保存为一个新版本，只保留最新的 keep 个，keep 为 0 时不保存
+pub fn backup_generation(dir: &Path, names: &[&str], keep: usize) -> error::Result<Option<PathBuf>> {
+    let existing = names.iter().filter(|x| dir.join(x).is_file()).collect::<Vec<_>>();
+    if keep == 0 || existing.is_empty() {
+        return Ok(None);
+    }
+    let output_error = |e: io::Error| Error::Output(format!("backup generation error {}, {}", dir.display(), e));
+    let name = chrono::Local::now().format("%Y%m%dT%H%M%S%.6f").to_string();
+    let generation = dir.join(GENERATIONS_DIR).join(&name);
+    // 先复制到临时目录再重命名，不会留下不完整的版本
+    let temp = dir.join(GENERATIONS_DIR).join(format!(".{}.tmp", name));
+    fs::create_dir_all(&temp).map_err(output_error)?;
+    for x in existing {
+        fs::copy(dir.join(x), temp.join(x)).map_err(output_error)?;
+    }
+    fs::write(temp.join(MANIFEST_NAME), names.join("\n")).map_err(output_error)?;
+    fs::rename(&temp, &generation).map_err(output_error)?;
+    let list = list_generations(dir)?;
+    for x in &list[..list.len().saturating_sub(keep)] {
+        fs::remove_dir_all(x).map_err(output_error)?;
+    }
+    Ok(Some(generation))
+}
+
+// 用最新的版本整组替换当前的输出文件，用过的版本会被删除，再次回滚会回到更早的版本
+// 清单中有而版本中没有的文件是之后才新增的输出，一起删除
+pub fn rollback_generation(dir: &Path) -> error::Result<PathBuf> {
+    let generation = list_generations(dir)?.pop()
+        .ok_or_else(|| Error::Output(format!("no generation found in {}", dir.join(GENERATIONS_DIR).display())))?;
+    let output_error = |e: io::Error| Error::Output(format!("rollback error {}, {}", generation.display(), e));
+    let mut files = vec![];
+    for entry in fs::read_dir(&generation).map_err(output_error)? {
+        let entry = entry.map_err(output_error)?;
+        if entry.file_name() == MANIFEST_NAME {
+            continue;
+        }
+        let content = fs::read(entry.path()).map_err(output_error)?;
+        files.push((dir.join(entry.file_name()), content));
+    }
+    // 没有清单的旧版本只恢复文件
+    let manifest = match fs::read_to_string(generation.join(MANIFEST_NAME)) {
+        Ok(x) => x,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::default(),
+        Err(e) => return Err(output_error(e)),
+    };
+    let added = manifest.lines()
+        .filter(|x| !x.is_empty() && Path::new(x).file_name() == Some(x.as_ref()))
+        .map(|x| dir.join(x))
+        .filter(|x| !files.iter().any(|(path, _)| path == x))
+        .collect::<Vec<_>>();
+    write_files(&files)?;
+    for path in added {
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(output_error(e)),
+            _ => {},
+        }
+    }
+    fs::remove_dir_all(&generation).map_err(output_error)?;
+    Ok(generation)
+}
+
+// 与现有文件相比新增和删除的行数
+pub fn line_changes(path: &Path, str: &str) -> String {
+    let old = match fs::read_to_string(path) {
+        Err(_) => return format!("new file, {} lines", str.lines().count()),
+        Ok(x) => x,
+    };
+    if old == str {
+        return "unchanged".to_string();
+    }
+    let mut counts: HashMap<&str, i64> = HashMap::new();
+    for line in old.lines() {
+        *counts.entry(line).or_default() -= 1;
+    }
+    for line in str.lines() {
+        *counts.entry(line).or_default() += 1;
+    }
+    let added: i64 = counts.values().filter(|&&x| x > 0).sum();
+    let removed: i64 = -counts.values().filter(|&&x| x < 0).sum::<i64>();
+    format!("+{} -{} lines", added, removed)
+}
+
 #[cfg(test)]
 mod file_util_test {
+    use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+
     use crate::{file_tool::*, extract::IpLocation};
 
+    // 只处理简单 GET 请求的本地 HTTP 服务，handler 根据请求头返回完整的响应
+    fn mock_server(handler: impl Fn(&str) -> Vec<u8> + Send + 'static) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = vec![];
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let response = handler(&String::from_utf8_lossy(&request).to_lowercase());
+                let _ = stream.write_all(&response);
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ip-extract-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn conditional_download_test() {
+        let url = mock_server(|request| {
+            if request.contains("if-none-match: \"v1\"") {
+                return b"HTTP/1.1 304 Not Modified\r\nContent-Length: 0\r\n\r\n".to_vec();
+            }
+            b"HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nLast-Modified: Mon, 01 Jan 2024 00:00:00 GMT\r\nContent-Length: 4\r\n\r\nPK\x03\x04".to_vec()
+        });
+        let path = temp_dir("conditional-download").join("test.zip");
+        assert_eq!(DownloadStatus::Downloaded, download_file(&url, &path, &test_options()).unwrap());
+        assert_eq!(b"PK\x03\x04".to_vec(), fs::read(&path).unwrap());
+        assert_eq!("etag=\"v1\"\nlast-modified=Mon, 01 Jan 2024 00:00:00 GMT\n", fs::read_to_string(meta_path(&path)).unwrap());
+        assert_eq!(DownloadStatus::NotModified, download_file(&url, &path, &test_options()).unwrap());
+
+        // 文件被删掉后不能再发条件请求
+        fs::remove_file(&path).unwrap();
+        assert_eq!(DownloadStatus::Downloaded, download_file(&url, &path, &test_options()).unwrap());
+    }
+
+    #[test]
+    fn download_error_status_test() {
+        let url = mock_server(|_| b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec());
+        let path = temp_dir("download-error-status").join("test.zip");
+        assert!(download_file(&url, &path, &test_options()).is_err());
+        assert!(!path.exists());
+    }
+
+    fn test_options() -> DownloadOptions {
+        DownloadOptions {
+            retries: 2,
+            retry_delay: Duration::from_millis(10),
+            connect_timeout: Duration::from_secs(5),
+            read_timeout: Duration::from_millis(500),
+            min_size: 4,
+            ..DownloadOptions::default()
+        }
+    }
+
+    #[test]
+    fn download_retry_test() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let server_count = count.clone();
+        let url = mock_server(move |_| {
+            if server_count.fetch_add(1, Ordering::SeqCst) == 0 {
+                return b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n".to_vec();
+            }
+            b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nPK\x03\x04".to_vec()
+        });
+        let path = temp_dir("download-retry").join("test.zip");
+        assert_eq!(DownloadStatus::Downloaded, download_file(&url, &path, &test_options()).unwrap());
+        assert_eq!(2, count.load(Ordering::SeqCst));
+        assert_eq!(b"PK\x03\x04".to_vec(), fs::read(&path).unwrap());
+        assert!(!with_suffix(&path, ".part").exists());
+    }
+
+    #[test]
+    fn retry_delay_test() {
+        assert_eq!(Duration::from_secs(5), retry_delay(Duration::from_secs(5), 0));
+        assert_eq!(Duration::from_secs(20), retry_delay(Duration::from_secs(5), 2));
+        assert_eq!(MAX_RETRY_DELAY, retry_delay(Duration::from_secs(5), 10));
+        // 2 的 32 次方和乘积溢出时不会 panic
+        assert_eq!(MAX_RETRY_DELAY, retry_delay(Duration::from_secs(5), 40));
+        assert_eq!(MAX_RETRY_DELAY, retry_delay(Duration::MAX, 1));
+    }
+
+    #[test]
+    fn download_client_error_not_retry_test() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let server_count = count.clone();
+        let url = mock_server(move |_| {
+            server_count.fetch_add(1, Ordering::SeqCst);
+            b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n".to_vec()
+        });
+        let path = temp_dir("download-client-error").join("test.zip");
+        assert!(download_file(&url, &path, &test_options()).is_err());
+        assert_eq!(1, count.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn download_resume_test() {
+        let requests = Arc::new(std::sync::Mutex::new(vec![]));
+        let server_requests = requests.clone();
+        let url = mock_server(move |request| {
+            server_requests.lock().unwrap().push(request.to_string());
+            if request.contains("range: bytes=5-") && request.contains("if-range: \"v1\"") {
+                return b"HTTP/1.1 206 Partial Content\r\nETag: \"v1\"\r\nContent-Range: bytes 5-9/10\r\nContent-Length: 5\r\n\r\n56789".to_vec();
+            }
+            // 声明 10 字节但只发 5 字节就断开
+            b"HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: 10\r\n\r\nPK\x03\x044".to_vec()
+        });
+        let path = temp_dir("download-resume").join("test.zip");
+        assert_eq!(DownloadStatus::Downloaded, download_file(&url, &path, &test_options()).unwrap());
+        assert_eq!(b"PK\x03\x04456789".to_vec(), fs::read(&path).unwrap());
+        assert_eq!(2, requests.lock().unwrap().len());
+        assert!(!with_suffix(&path, ".part").exists());
+        assert!(!meta_path(&with_suffix(&path, ".part")).exists());
+        assert_eq!("etag=\"v1\"\n", fs::read_to_string(meta_path(&path)).unwrap());
+    }
+
+    #[test]
+    fn download_timeout_test() {
+        let url = mock_server(|_| {
+            std::thread::sleep(Duration::from_millis(800));
+            b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nPK\x03\x04".to_vec()
+        });
+        let path = temp_dir("download-timeout").join("test.zip");
+        let options = DownloadOptions { retries: 0, ..test_options() };
+        let error = download_file(&url, &path, &options).unwrap_err();
+        assert!(error.to_string().contains("timed out"));
+        assert!(!path.exists());
+    }
+
+    fn validate(content: &[u8], options: &DownloadOptions) -> Result<(), DownloadError> {
+        let path = temp_dir("validate").join(format!("{}.zip", options.min_size));
+        fs::write(&path, content).unwrap();
+        validate_download(&path, options).map_err(|e| *e.downcast::<DownloadError>().unwrap())
+    }
+
+    #[test]
+    fn validate_download_test() {
+        let options = test_options();
+        assert_eq!(Ok(()), validate(b"PK\x03\x04", &options));
+        assert!(matches!(validate(b"INVALID TOKEN.", &options), Err(DownloadError::InvalidToken(_))));
+        assert!(matches!(validate(b"NO PERMISSION", &options), Err(DownloadError::InvalidToken(_))));
+        assert!(matches!(validate(b"THIS FILE CAN ONLY BE DOWNLOADED 5 TIMES PER HOUR.", &options), Err(DownloadError::QuotaExceeded(_))));
+        assert!(matches!(validate(b"<html><body>Bad Gateway</body></html>", &options), Err(DownloadError::NotZip(_))));
+        assert_eq!(Err(DownloadError::TooSmall { size: 4, min_size: 4096 }), validate(b"PK\x03\x04", &DownloadOptions::default()));
+
+        let sha256 = "bd3ef16f2afdf8e2a5ba2b2f5c0fd8c1a0cc8ed5db5b3c8d08e4d7e1ed0a28d2".to_string();
+        let options = DownloadOptions { sha256: Some(sha256.clone()), ..test_options() };
+        let actual = match validate(b"PK\x03\x04", &options) {
+            Err(DownloadError::ChecksumMismatch { expected, actual }) => {
+                assert_eq!(sha256, expected);
+                actual
+            },
+            x => panic!("unexpected result {:?}", x),
+        };
+        let options = DownloadOptions { sha256: Some(actual.to_uppercase()), ..test_options() };
+        assert_eq!(Ok(()), validate(b"PK\x03\x04", &options));
+    }
+
+    #[test]
+    fn download_error_page_test() {
+        let url = mock_server(|_| b"HTTP/1.1 200 OK\r\nContent-Length: 13\r\n\r\nNO PERMISSION".to_vec());
+        let dir = temp_dir("download-error-page");
+        let path = dir.join("test.zip");
+        fs::write(&path, "old").unwrap();
+        let error = download_file(&url, &path, &test_options()).unwrap_err();
+        assert!(matches!(error, Error::Validation(_)));
+        assert!(error.to_string().contains("invalid IP2LOCATION_TOKEN"), "{}", error);
+        // 校验失败时保留原来的文件
+        assert_eq!("old", fs::read_to_string(&path).unwrap());
+        assert!(!with_suffix(&path, ".part").exists());
+    }
+
+    const TEST_CERTIFICATE: &str = "-----BEGIN CERTIFICATE-----\n\
+MIIDHTCCAgWgAwIBAgIUHqmwWfC+H/31AAFc04srrSK6Ms0wDQYJKoZIhvcNAQEL\n\
+BQAwHTEbMBkGA1UEAwwSaXAtZXh0cmFjdCB0ZXN0IENBMCAXDTI2MTAxOTA3MzIx\n\
+M1oYDzIxMjYwOTI1MDczMjEzWjAdMRswGQYDVQQDDBJpcC1leHRyYWN0IHRlc3Qg\n\
+Q0EwggEiMA0GCSqGSIb3DQEBAQUAA4IBDwAwggEKAoIBAQC1aHkDSiZTn48/hLym\n\
+jCnTfRCc8oRuv78v9zODpvKuWOwvsHU+Vx6AkMUBkJ6v8QijzaKQAUjjPTogGinx\n\
+HK1bdHjwn3au+2OKDi4Wk0QYDQ2BspcKwwMbLVBR2HMQ02kSSv6DhkzYU+cgKNsq\n\
+UvP3pUyu5tT/9JooPJejCZD3jCWAGSZD8qnJCq1T45emqo3M75HVkeZ/LrGeZ9Jk\n\
+6B/DD8PxyIlVdYE+G0LzSAWwVI8KuY+yoC8TX4dLii8ESTmtaHyFW0hE0zN66JZj\n\
+shaetKx8Li/wMhJgAbVwgRAHRZlBhfquAd2lAeIq9pOjLKMpQES7rmSa8FB2bb10\n\
+j/GrAgMBAAGjUzBRMB0GA1UdDgQWBBTgg5eSjogshdyXecSMiYUgtslrzjAfBgNV\n\
+HSMEGDAWgBTgg5eSjogshdyXecSMiYUgtslrzjAPBgNVHRMBAf8EBTADAQH/MA0G\n\
+CSqGSIb3DQEBCwUAA4IBAQC0m8k1vc0xhQpK+uArwfYrpwPGYetVEugbsDC+1bdp\n\
+HJAcIRjuU5YA56vIFW3qbTQInuZWycAV2hgL8VX2eDYIGPgD+Yt6mAGK6HiJedDJ\n\
+WZIL1CPVW2SssuM3s1eK5zjgY51XwwkwRbrFbuT9fjFPsRjad+TvyzNioyCYuia/\n\
+pC4jgUmIPSiEijI5v4vQXfBxZ2PTi1OgFKhnah0122z4VT5HhqRg3ULpFdqheE6M\n\
+qIH5UHBuIgXWK5bzrnOmWMe8umiBWZRyf57CrA/Q5EW+KYkonqBOZg/DlbeDG5sx\n\
+uIE6ct73dSXLl01YmhcJyWOB3bWj8hUFuktdg+Gfv60D\n\
+-----END CERTIFICATE-----";
+
+    #[test]
+    fn build_client_test() {
+        let dir = temp_dir("build-client");
+        let bundle = dir.join("bundle.pem");
+        fs::write(&bundle, format!("# comment\n{}\n{}\n", TEST_CERTIFICATE, TEST_CERTIFICATE)).unwrap();
+        assert_eq!(2, pem_certificates(&fs::read_to_string(&bundle).unwrap()).len());
+        let options = DownloadOptions {
+            proxy: Some("socks5h://127.0.0.1:1080".to_string()),
+            ca_bundles: vec![bundle],
+            ..test_options()
+        };
+        assert!(build_client(&options).is_ok());
+
+        let empty = dir.join("empty.pem");
+        fs::write(&empty, "nothing").unwrap();
+        assert!(build_client(&DownloadOptions { ca_bundles: vec![empty], ..test_options() }).is_err());
+        assert!(build_client(&DownloadOptions { ca_bundles: vec![dir.join("missing.pem")], ..test_options() }).is_err());
+        assert!(build_client(&DownloadOptions { proxy: Some("not a url".to_string()), ..test_options() }).is_err());
+    }
+
+    #[test]
+    fn download_through_proxy_test() {
+        // 经过 HTTP 代理时请求行是完整的 URL
+        let url = mock_server(|request| {
+            if request.starts_with("get http://ip2location.invalid/db1.zip ") && request.contains("user-agent: test-agent") {
+                return b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nPK\x03\x04".to_vec();
+            }
+            b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n".to_vec()
+        });
+        let path = temp_dir("download-through-proxy").join("test.zip");
+        let options = DownloadOptions {
+            proxy: Some(url),
+            user_agent: "test-agent".to_string(),
+            ..test_options()
+        };
+        assert_eq!(DownloadStatus::Downloaded, download_file("http://ip2location.invalid/db1.zip", &path, &options).unwrap());
+    }
+
     #[test]
     fn download_file_test() {
         let url = "https://www.ip2location.com/download/?token=Bpty9cFpYzCxnL5F2Fhkqxu9FVZm0tT1GRN66ycs3w6xJv7qD1Gc2HeCiCMcfFqc&file=DB1LITECSVIPV6";
         let path = Path::new("/data/test/test2/IP2LOCATION-LITE-DB1.IPV6.CSV.ZIP");
-        download_file(url, path).unwrap_or_else(|e| {
+        download_file(url, path, &DownloadOptions::default()).unwrap_or_else(|e| {
             panic!("download file error {}", e)
         });
         assert_eq!(true, path.exists());
     }
     
+    fn create_zip(path: &Path, entries: &[(&str, &str)]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        for (name, content) in entries {
+            zip.start_file(*name, zip::write::FileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn unzip_member_test() {
+        let dir = temp_dir("unzip-member");
+        let source = dir.join("db.zip");
+        create_zip(&source, &[("README_LITE.TXT", "readme"), ("LICENSE_LITE.TXT", "license"), ("data/DB1.CSV", "\"0\",\"1\",\"-\",\"-\"")]);
+        let target = dir.join("out");
+        let path = unzip(&source, &target, "DB1.CSV").unwrap();
+        assert_eq!(target.join("DB1.CSV"), path);
+        assert_eq!("\"0\",\"1\",\"-\",\"-\"", fs::read_to_string(&path).unwrap());
+        // 其他文件不会被解压
+        assert_eq!(1, fs::read_dir(&target).unwrap().count());
+        assert!(unzip(&source, &target, "MISSING.CSV").is_err());
+    }
+
+    #[test]
+    fn unzip_malicious_test() {
+        let dir = temp_dir("unzip-malicious");
+        let source = dir.join("evil.zip");
+        create_zip(&source, &[("../DB1.CSV", "evil"), ("/tmp/DB1.CSV", "evil"), ("a/../../DB1.CSV", "evil")]);
+        let target = dir.join("out");
+        assert!(unzip(&source, &target, "DB1.CSV").is_err());
+        assert!(!dir.join("DB1.CSV").exists());
+        assert!(!target.join("DB1.CSV").exists());
+
+        // 不安全的条目被跳过，安全的同名文件仍然可以解压
+        create_zip(&source, &[("../DB1.CSV", "evil"), ("DB1.CSV", "good")]);
+        assert_eq!("good", fs::read_to_string(unzip(&source, &target, "DB1.CSV").unwrap()).unwrap());
+        assert!(!dir.join("DB1.CSV").exists());
+    }
+
+    #[test]
+    fn unzip_invalid_test() {
+        let dir = temp_dir("unzip-invalid");
+        let source = dir.join("broken.zip");
+        fs::write(&source, "NO PERMISSION").unwrap();
+        assert!(unzip(&source, &dir.join("out"), "DB1.CSV").is_err());
+        assert!(unzip(&dir.join("missing.zip"), &dir.join("out"), "DB1.CSV").is_err());
+        fs::write(dir.join("file"), "").unwrap();
+        create_zip(&source, &[("DB1.CSV", "good")]);
+        assert!(unzip(&source, &dir.join("file"), "DB1.CSV").is_err());
+    }
+
+    const CSV: &str = "\"16777216\",\"16777471\",\"US\",\"United States of America\"\n\"16777472\",\"16778239\",\"CN\",\"China\"\n";
+
+    #[test]
+    fn read_csv_input_test() {
+        let dir = temp_dir("read-csv-input");
+        let csv = dir.join("DB1.CSV");
+        fs::write(&csv, CSV).unwrap();
+        let zip = dir.join("DB1.CSV.ZIP");
+        create_zip(&zip, &[("README_LITE.TXT", "readme"), ("DB1.CSV", CSV)]);
+        let gz = dir.join("DB1.CSV.gz");
+        let mut encoder = flate2::write::GzEncoder::new(File::create(&gz).unwrap(), flate2::Compression::default());
+        encoder.write_all(CSV.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        for path in [&csv, &zip, &gz] {
+            let list = read_csv_input::<IpLocation>(path.to_str().unwrap(), "DB1.CSV", true).unwrap();
+            assert_eq!(2, list.len());
+            assert_eq!("CN", list[1].country_code);
+        }
+        assert!(read_csv_input::<IpLocation>(zip.to_str().unwrap(), "DB2.CSV", false).is_err());
+        // ZIP 中直接读取，不会解压出文件
+        assert_eq!(3, fs::read_dir(&dir).unwrap().count());
+    }
+
+    #[test]
+    fn read_csv_from_test() {
+        let list = read_csv_from::<IpLocation, _>(CSV.as_bytes(), true).unwrap();
+        assert_eq!(2, list.len());
+        assert_eq!("16777216", list[0].ip_start);
+    }
+
+    #[test]
+    fn split_csv_line_test() {
+        assert_eq!(Some(vec!["1".to_string(), "2".to_string(), "KR".to_string(), "Korea, Republic of".to_string()]),
+            split_csv_line("\"1\",\"2\",\"KR\",\"Korea, Republic of\""));
+        assert_eq!(Some(vec!["a \"b\"".to_string(), "".to_string(), "c".to_string()]), split_csv_line("\"a \"\"b\"\"\",,c"));
+        assert_eq!(Some(vec!["".to_string()]), split_csv_line(""));
+        assert_eq!(Some(vec!["a\nb".to_string()]), split_csv_line("\"a\nb\""));
+        assert_eq!(None, split_csv_line("\"unterminated"));
+        assert_eq!(None, split_csv_line("\"a\"b,c"));
+        assert_eq!(None, split_csv_line("a\"b"));
+    }
+
+    #[test]
+    fn read_csv_malformed_test() {
+        let csv = "\"1\",\"2\",\"KR\",\"Korea, Republic of\"\r\n\
+            \"3\",\"4\",\"US\"\n\
+            \n\
+            \"5\",\"6\",\"IR\",\"Iran,\nIslamic Republic of\"\n\
+            \"7\",\"8\",\"X\"Y\",\"bad\"\n";
+        let list = read_csv_from::<IpLocation, _>(csv.as_bytes(), false).unwrap();
+        assert_eq!(2, list.len());
+        assert_eq!("Korea, Republic of", list[0].country_name);
+        assert_eq!("Iran,\nIslamic Republic of", list[1].country_name);
+        let e = read_csv_from::<IpLocation, _>(csv.as_bytes(), true).err().unwrap();
+        assert!(e.to_string().contains("line 2"), "{}", e);
+        assert!(read_csv_from::<IpLocation, _>("\"1\",\"2\",\"US\",\"open\n".as_bytes(), true).is_err());
+
+        // 中间一行的引号没有闭合，后面的行仍然正常解析
+        let csv = "\"1\",\"2\",\"KR\",\"Korea\"\n\
+            \"3\",\"4\",\"US\",\"United States\n\
+            \"5\",\"6\",\"JP\",\"Japan\"\n\
+            \"7\",\"8\",\"X\"Y\",\"bad\"\n\
+            \"9\",\"10\",\"CN\",\"China\"\n";
+        let list = read_csv_from::<IpLocation, _>(csv.as_bytes(), false).unwrap();
+        assert_eq!(vec!["KR", "JP", "CN"], list.iter().map(|x| x.country_code.as_str()).collect::<Vec<_>>());
+        // 最后一行的引号没有闭合
+        let csv = format!("\"1\",\"2\",\"KR\",\"Korea\n{}", "\"3\",\"4\",\"US\",\"United States\"\n".repeat(20));
+        assert_eq!(20, read_csv_from::<IpLocation, _>(csv.as_bytes(), false).unwrap().len());
+        let e = read_csv_from::<IpLocation, _>(csv.as_bytes(), true).err().unwrap();
+        assert!(e.to_string().contains("line 1"), "{}", e);
+    }
+
     #[test]
     fn unzip_test() {
         let path = Path::new("/data/test/test2/IP2LOCATION-LITE-DB1.IPV6.CSV.ZIP");
         let target_path = Path::new("/data/test/");
-        unzip(&path, &target_path).unwrap_or_else(|e| {
+        unzip(path, target_path, "IP2LOCATION-LITE-DB1.IPV6.CSV").unwrap_or_else(|e| {
             panic!("unzip file error {}", e)
         });
 
@@ -120,7 +1075,7 @@ mod file_util_test {
     #[test]
     fn read_csv_test() {
         let file_path = Path::new("/data/test/IP2LOCATION-LITE-DB1.IPV6.CSV");
-        let list: Vec<IpLocation> = read_csv::<IpLocation>(&file_path).unwrap_or_else(|e| {
+        let list: Vec<IpLocation> = read_csv::<IpLocation>(file_path, false).unwrap_or_else(|e| {
             panic!("read csv file error {}", e)
         });
     
@@ -145,4 +1100,68 @@ mod file_util_test {
     
         assert_eq!("123789456", result);
     }
+
+    #[test]
+    fn write_files_test() {
+        let dir = temp_dir("write-files");
+        write_files(&[(dir.join("a.txt"), "a".to_string()), (dir.join("b.txt"), "b".to_string())]).unwrap();
+        assert_eq!("a", fs::read_to_string(dir.join("a.txt")).unwrap());
+        assert_eq!("b", fs::read_to_string(dir.join("b.txt")).unwrap());
+        // 有一个文件写不了时其他文件都不替换
+        fs::create_dir_all(dir.join("c.txt")).unwrap();
+        fs::create_dir_all(dir.join(".c.txt.tmp")).unwrap();
+        assert!(write_files(&[(dir.join("a.txt"), "a2".to_string()), (dir.join("c.txt"), "c".to_string())]).is_err());
+        assert_eq!("a", fs::read_to_string(dir.join("a.txt")).unwrap());
+        assert!(!dir.join(".a.txt.tmp").exists());
+    }
+
+    #[test]
+    fn generation_test() {
+        let dir = temp_dir("generation");
+        let names = ["a.txt", "b.txt"];
+        assert_eq!(None, backup_generation(&dir, &names, 2).unwrap());
+        assert!(rollback_generation(&dir).is_err());
+
+        for i in 1..=3 {
+            write_files(&names.map(|x| (dir.join(x), format!("{}{}", x, i)))).unwrap();
+            if i < 3 {
+                assert!(backup_generation(&dir, &names, 2).unwrap().is_some());
+            }
+        }
+        assert_eq!(None, backup_generation(&dir, &names, 0).unwrap());
+        assert!(backup_generation(&dir, &names, 2).unwrap().is_some());
+        // 只保留最新的 2 个版本，分别是第 2 次和第 3 次的输出
+        assert_eq!(2, list_generations(&dir).unwrap().len());
+        write_files(&names.map(|x| (dir.join(x), format!("{}4", x)))).unwrap();
+
+        rollback_generation(&dir).unwrap();
+        assert_eq!("a.txt3", fs::read_to_string(dir.join("a.txt")).unwrap());
+        assert_eq!("b.txt3", fs::read_to_string(dir.join("b.txt")).unwrap());
+        rollback_generation(&dir).unwrap();
+        assert_eq!("a.txt2", fs::read_to_string(dir.join("a.txt")).unwrap());
+        assert_eq!("b.txt2", fs::read_to_string(dir.join("b.txt")).unwrap());
+        assert!(rollback_generation(&dir).is_err());
+
+        // 新增的输出在回滚时删除，二进制内容原样恢复
+        let dir = temp_dir("generation-added");
+        write_files(&[(dir.join("a.txt"), vec![0xff, 0xfe, 0x00])]).unwrap();
+        let names = ["a.txt", "b.txt"];
+        assert!(backup_generation(&dir, &names, 2).unwrap().is_some());
+        write_files(&names.map(|x| (dir.join(x), x.as_bytes().to_vec()))).unwrap();
+        rollback_generation(&dir).unwrap();
+        assert_eq!(vec![0xff, 0xfe, 0x00], fs::read(dir.join("a.txt")).unwrap());
+        assert!(!dir.join("b.txt").exists());
+        assert!(!dir.join(MANIFEST_NAME).exists());
+    }
+
+    #[test]
+    fn line_changes_test() {
+        let file_path = std::env::temp_dir().join(format!("ip-extract-line-changes-{}.txt", std::process::id()));
+        assert_eq!("new file, 2 lines", line_changes(&file_path, "a\nb\n"));
+        write_file(&file_path, "a\nb\nc\n".to_string()).unwrap();
+        assert_eq!("unchanged", line_changes(&file_path, "a\nb\nc\n"));
+        assert_eq!("+2 -1 lines", line_changes(&file_path, "a\nc\nd\ne\n"));
+        assert!(!file_path.parent().unwrap().join(format!(".{}.tmp", file_path.file_name().unwrap().to_string_lossy())).exists());
+        fs::remove_file(&file_path).unwrap();
+    }
 }
\ No newline at end of file
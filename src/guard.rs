@@ -0,0 +1,250 @@
+use std::{fs, net::IpAddr, path::Path};
+
+use crate::{error::{self, Error}, file_tool, ip_tool};
+
+// 替换输出文件前的检查，任何一项不通过都不会发布新的输出
+#[derive(Debug, Default)]
+pub struct Guardrails {
+    // CSV 中至少要有的行数
+    pub min_rows: usize,
+    // 提取出的地址段至少要覆盖的地址个数
+    pub min_addresses: u128,
+    // 与上一次运行相比，覆盖的地址个数最多变化的百分比
+    pub max_change_percent: Option<f64>,
+    // 必须在提取结果中的地址
+    pub required_in: Vec<IpAddr>,
+    // 必须不在提取结果中的地址
+    pub required_out: Vec<IpAddr>,
+}
+
+// profile 中单独设置的检查项目，None 时沿用环境变量
+#[derive(Debug, Clone, Default)]
+pub struct ProfileGuardrails {
+    pub min_addresses: Option<u128>,
+    pub max_change_percent: Option<f64>,
+    pub required_in: Option<Vec<IpAddr>>,
+    pub required_out: Option<Vec<IpAddr>>,
+}
+
+impl Guardrails {
+    pub fn with(&self, profile: &ProfileGuardrails) -> Guardrails {
+        Guardrails {
+            min_rows: self.min_rows,
+            min_addresses: profile.min_addresses.unwrap_or(self.min_addresses),
+            max_change_percent: profile.max_change_percent.or(self.max_change_percent),
+            required_in: profile.required_in.clone().unwrap_or_else(|| self.required_in.clone()),
+            required_out: profile.required_out.clone().unwrap_or_else(|| self.required_out.clone()),
+        }
+    }
+}
+
+// 一次运行的统计，发布成功后保存到输出目录下的 .state 文件，供下一次比较
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunStats {
+    pub ipv6: bool,
+    pub rows: usize,
+    pub ranges: usize,
+    pub addresses: u128,
+}
+
+impl RunStats {
+    pub fn new(rows: usize, ranges: &[(u128, u128)], ipv6: bool) -> RunStats {
+        RunStats {
+            ipv6,
+            rows,
+            ranges: ranges.len(),
+            // 整个 IPv6 地址空间的个数超出 u128，按最大值计
+            addresses: ranges.iter().fold(0_u128, |sum, &(start, end)| sum.saturating_add((end - start).saturating_add(1))),
+        }
+    }
+}
+
+// 每个 profile 一节，以 [name] 开头
+pub const STATE_NAME: &str = ".state";
+
+fn parse_stats(lines: &[&str]) -> Option<RunStats> {
+    let get = |key: &str| lines.iter().filter_map(|x| x.split_once('=')).find(|(k, _)| *k == key).map(|(_, v)| v.to_string());
+    Some(RunStats {
+        ipv6: get("ipv6")?.parse().ok()?,
+        rows: get("rows")?.parse().ok()?,
+        ranges: get("ranges")?.parse().ok()?,
+        addresses: get("addresses")?.parse().ok()?,
+    })
+}
+
+// 每个 profile 上一次发布时的统计，无法解析的节被忽略
+pub fn read_state(dir: &Path) -> Vec<(String, RunStats)> {
+    let content = fs::read_to_string(dir.join(STATE_NAME)).unwrap_or_default();
+    let mut sections: Vec<(&str, Vec<&str>)> = vec![];
+    for line in content.lines() {
+        if let Some(name) = line.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+            sections.push((name, vec![]));
+        } else if let Some((_, lines)) = sections.last_mut() {
+            lines.push(line);
+        }
+    }
+    sections.into_iter().filter_map(|(name, lines)| Some((name.to_string(), parse_stats(&lines)?))).collect()
+}
+
+pub fn write_state(dir: &Path, list: &[(String, RunStats)]) -> error::Result<()> {
+    let content = list.iter()
+        .map(|(name, x)| format!("[{}]\nipv6={}\nrows={}\nranges={}\naddresses={}\n", name, x.ipv6, x.rows, x.ranges, x.addresses))
+        .collect::<String>();
+    file_tool::write_file(&dir.join(STATE_NAME), content)
+}
+
+// 上一次成功发布时的输入和配置指纹，下载文件未修改且指纹相同时才跳过
+pub const FINGERPRINT_NAME: &str = ".fingerprint";
+
+pub fn read_fingerprint(dir: &Path) -> Option<String> {
+    fs::read_to_string(dir.join(FINGERPRINT_NAME)).ok().map(|x| x.trim().to_string())
+}
+
+pub fn write_fingerprint(dir: &Path, fingerprint: &str) -> error::Result<()> {
+    file_tool::write_file(&dir.join(FINGERPRINT_NAME), format!("{}\n", fingerprint))
+}
+
+// ranges 需要按起始地址排序
+fn contains(ranges: &[(u128, u128)], number: u128) -> bool {
+    match ranges.partition_point(|&(start, _)| start <= number).checked_sub(1) {
+        None => false,
+        Some(index) => number <= ranges[index].1,
+    }
+}
+
+// 检查一个 profile 的所有项目，返回全部失败原因
+pub fn failures(guardrails: &Guardrails, stats: &RunStats, previous: Option<&RunStats>, ranges: &[(u128, u128)]) -> Vec<String> {
+    let mut failures = vec![];
+    if stats.rows < guardrails.min_rows {
+        failures.push(format!("csv rows {} < MIN_ROWS {}", stats.rows, guardrails.min_rows));
+    }
+    if stats.addresses < guardrails.min_addresses {
+        failures.push(format!("covered addresses {} < MIN_ADDRESSES {}", stats.addresses, guardrails.min_addresses));
+    }
+    if let (Some(max), Some(previous)) = (guardrails.max_change_percent, previous) {
+        // 数据集不同时没有可比性
+        if previous.ipv6 == stats.ipv6 && previous.addresses > 0 {
+            let percent = stats.addresses.abs_diff(previous.addresses) as f64 * 100.0 / previous.addresses as f64;
+            if percent > max {
+                failures.push(format!("covered addresses changed {:.2}% ({} -> {}, ranges {} -> {}) > MAX_CHANGE_PERCENT {}",
+                    percent, previous.addresses, stats.addresses, previous.ranges, stats.ranges, max));
+            }
+        }
+    }
+    for (ips, expected, key) in [(&guardrails.required_in, true, "REQUIRED_IN"), (&guardrails.required_out, false, "REQUIRED_OUT")] {
+        for &ip in ips {
+            match ip_tool::ip_to_number(ip, stats.ipv6) {
+                None => failures.push(format!("{} {} can not be checked against an ipv4 dataset", key, ip)),
+                Some(x) if contains(ranges, x) != expected => {
+                    failures.push(format!("{} {} is {} the extracted ranges", key, ip, if expected { "not in" } else { "in" }));
+                },
+                Some(_) => {},
+            }
+        }
+    }
+    failures
+}
+
+// 有失败原因时返回列出全部原因的 Validation 错误
+pub fn report(failures: &[String]) -> error::Result<()> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+    Err(Error::Validation(format!("guardrail check failed, keep last outputs:\n  - {}", failures.join("\n  - "))))
+}
+
+#[cfg(test)]
+mod guard_test {
+    use std::net::IpAddr;
+
+    use crate::{error::{self, Error}, guard::*};
+
+    fn ip(str: &str) -> IpAddr {
+        str.parse().unwrap()
+    }
+
+    fn check(guardrails: &Guardrails, stats: &RunStats, previous: Option<&RunStats>, ranges: &[(u128, u128)]) -> error::Result<()> {
+        report(&failures(guardrails, stats, previous, ranges))
+    }
+
+    #[test]
+    fn check_test() {
+        let ranges = vec![(16777216, 16777471), (16778240, 16779263)];
+        let stats = RunStats::new(3, &ranges, false);
+        assert_eq!(1280, stats.addresses);
+        assert!(check(&Guardrails::default(), &stats, None, &ranges).is_ok());
+
+        let guardrails = Guardrails {
+            min_rows: 3,
+            min_addresses: 1280,
+            max_change_percent: Some(10.0),
+            required_in: vec![ip("1.0.0.1"), ip("::ffff:1.0.4.1")],
+            required_out: vec![ip("1.0.1.1")],
+        };
+        let previous = RunStats { addresses: 1200, ..stats.clone() };
+        assert!(check(&guardrails, &stats, Some(&previous), &ranges).is_ok());
+
+        let guardrails = Guardrails {
+            min_rows: 4,
+            min_addresses: 2000,
+            max_change_percent: Some(5.0),
+            required_in: vec![ip("1.0.1.1"), ip("2001:200::1")],
+            required_out: vec![ip("1.0.0.1")],
+        };
+        let message = match check(&guardrails, &stats, Some(&previous), &ranges) {
+            Err(Error::Validation(x)) => x,
+            x => panic!("unexpected result {:?}", x),
+        };
+        assert_eq!(6, message.lines().count() - 1, "{}", message);
+        assert!(message.contains("MIN_ROWS"));
+        assert!(message.contains("MIN_ADDRESSES"));
+        assert!(message.contains("changed 6.67%"));
+        assert!(message.contains("REQUIRED_IN 1.0.1.1 is not in"));
+        assert!(message.contains("REQUIRED_IN 2001:200::1 can not be checked"));
+        assert!(message.contains("REQUIRED_OUT 1.0.0.1 is in"));
+
+        // 上一次是另一个数据集时不比较变化
+        let previous = RunStats { ipv6: true, addresses: 1, ..stats.clone() };
+        assert!(check(&Guardrails { max_change_percent: Some(5.0), ..Guardrails::default() }, &stats, Some(&previous), &ranges).is_ok());
+    }
+
+    #[test]
+    fn profile_guardrails_test() {
+        let defaults = Guardrails { min_rows: 3, min_addresses: 100, required_in: vec![ip("8.8.8.8")], ..Guardrails::default() };
+        let guardrails = defaults.with(&ProfileGuardrails { required_in: Some(vec![]), max_change_percent: Some(5.0), ..ProfileGuardrails::default() });
+        assert_eq!(3, guardrails.min_rows);
+        assert_eq!(100, guardrails.min_addresses);
+        assert_eq!(Some(5.0), guardrails.max_change_percent);
+        assert!(guardrails.required_in.is_empty());
+        assert_eq!(vec![ip("8.8.8.8")], defaults.with(&ProfileGuardrails::default()).required_in);
+    }
+
+    #[test]
+    fn ipv6_dataset_test() {
+        let ranges = vec![(0xffff_0100_0000, 0xffff_0100_00ff), (42540528726795050063891204319802818560, 42540528806023212578155541913346768895)];
+        let stats = RunStats::new(2, &ranges, true);
+        let guardrails = Guardrails {
+            required_in: vec![ip("1.0.0.1"), ip("2001:200::1")],
+            required_out: vec![ip("1.0.1.1"), ip("::1")],
+            ..Guardrails::default()
+        };
+        assert!(check(&guardrails, &stats, None, &ranges).is_ok());
+    }
+
+    #[test]
+    fn state_test() {
+        let dir = std::env::temp_dir().join(format!("ip-extract-state-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(read_state(&dir).is_empty());
+        let list = vec![
+            ("non-cn".to_string(), RunStats::new(10, &[(0, u128::MAX - 1)], true)),
+            ("jp".to_string(), RunStats::new(10, &[(0, 255)], true)),
+        ];
+        write_state(&dir, &list).unwrap();
+        assert_eq!(list, read_state(&dir));
+        // 没有节名的旧格式和不完整的节被忽略
+        std::fs::write(dir.join(STATE_NAME), "ipv6=true\nrows=1\nranges=1\naddresses=1\n[a]\nrows=1\n").unwrap();
+        assert!(read_state(&dir).is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,55 +1,308 @@
-#[allow(dead_code)]
-pub fn ipv4_to_u32(ipv4: &str) -> Option<u32> {
-    let split = ipv4.split(".").collect::<Vec<&str>>();
-    if split.len() != 4 {
-        return None;
+// IPv6 数据集中 IPv4 地址映射到 ::ffff:0:0/96
+pub const MAPPED: u128 = 0xffff_0000_0000;
+pub const MAPPED_END: u128 = MAPPED | u32::MAX as u128;
+
+// 解析地址的模式，默认严格，接受的写法与 std::net 相同
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParseMode {
+    #[default]
+    Strict,
+    // 另外允许首尾空白、IPv4 中带前导 0 的十进制数、IPv6 的 [] 和 %zone，以及把单独的 IPv4 地址当作 ::ffff:0:0/96 中的地址
+    Lenient,
+}
+
+// 地址无法解析的具体原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddrError {
+    Empty,
+    OctetCount(usize),
+    // 第几个数为空，从 1 开始
+    EmptyOctet(usize),
+    InvalidOctet(String),
+    LeadingZero(String),
+    OctetOutOfRange(String),
+    MultipleDoubleColon,
+    // 单独的 ':' 在开头或结尾，或者连续三个 ':'
+    EmptyGroup,
+    TooManyGroups(usize),
+    TooFewGroups(usize),
+    GroupTooLong(String),
+    InvalidGroup(String),
+    // 点分的 IPv4 只能放在最后 32 位
+    MisplacedIpv4(String),
+    // 以下为 inet_aton 写法中的错误
+    PartCount(usize),
+    EmptyPart(usize),
+    InvalidPart(String),
+    PartOutOfRange(String),
+}
+
+impl std::fmt::Display for AddrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AddrError::Empty => write!(f, "empty address"),
+            AddrError::OctetCount(x) => write!(f, "expected 4 octets, found {}", x),
+            AddrError::EmptyOctet(x) => write!(f, "octet {} is empty", x),
+            AddrError::InvalidOctet(x) => write!(f, "octet '{}' is not a decimal number", x),
+            AddrError::LeadingZero(x) => write!(f, "octet '{}' has a leading zero", x),
+            AddrError::OctetOutOfRange(x) => write!(f, "octet '{}' is greater than 255", x),
+            AddrError::MultipleDoubleColon => write!(f, "'::' appears more than once"),
+            AddrError::EmptyGroup => write!(f, "empty group, a single ':' at the start or end or ':::'"),
+            AddrError::TooManyGroups(x) => write!(f, "too many groups ({})", x),
+            AddrError::TooFewGroups(x) => write!(f, "expected 8 groups without '::', found {}", x),
+            AddrError::GroupTooLong(x) => write!(f, "group '{}' has more than 4 hex digits", x),
+            AddrError::InvalidGroup(x) => write!(f, "group '{}' is not hexadecimal", x),
+            AddrError::MisplacedIpv4(x) => write!(f, "dotted ipv4 '{}' must be the last 32 bits", x),
+            AddrError::PartCount(x) => write!(f, "expected 1 to 4 parts, found {}", x),
+            AddrError::EmptyPart(x) => write!(f, "part {} is empty", x),
+            AddrError::InvalidPart(x) => write!(f, "part '{}' is not a decimal, octal or hex number", x),
+            AddrError::PartOutOfRange(x) => write!(f, "part '{}' is out of range", x),
+        }
     }
-    let mut number: u32 = match split.get(0)?.parse::<u32>() {
-        Err(_) => None,
-        Ok(x) => Some(x),
-    }?;
-    for i in 1..4 {
-        number = number << 8;
-        number = number | match split.get(i)?.parse::<u32>() {
-            Err(_) => None,
-            Ok(x) => Some(x),
-        }?;
+}
+
+pub fn parse_ipv4(text: &str, mode: ParseMode) -> Result<u32, AddrError> {
+    let text = if mode == ParseMode::Lenient { text.trim() } else { text };
+    if text.is_empty() {
+        return Err(AddrError::Empty);
+    }
+    let octets = text.split('.').collect::<Vec<_>>();
+    if octets.len() != 4 {
+        return Err(AddrError::OctetCount(octets.len()));
+    }
+    let mut number = 0_u32;
+    for (i, octet) in octets.into_iter().enumerate() {
+        if octet.is_empty() {
+            return Err(AddrError::EmptyOctet(i + 1));
+        }
+        // u32::from_str 接受 '+'，这里只允许数字
+        if !octet.bytes().all(|x| x.is_ascii_digit()) {
+            return Err(AddrError::InvalidOctet(octet.to_string()));
+        }
+        if mode == ParseMode::Strict && octet.len() > 1 && octet.starts_with('0') {
+            return Err(AddrError::LeadingZero(octet.to_string()));
+        }
+        let digits = octet.trim_start_matches('0');
+        let value = if digits.len() > 3 { None } else { digits.parse::<u32>().ok().or(Some(0)).filter(|&x| x <= 255) };
+        let value = value.ok_or_else(|| AddrError::OctetOutOfRange(octet.to_string()))?;
+        number = number << 8 | value;
     }
-    Some(number)
+    Ok(number)
 }
 
-#[allow(dead_code)]
-pub fn ipv6_to_u128(ipv6: &str) -> Option<u128> {
-    if ipv6.contains(".") {
-        let ipv4 = match ipv6.rfind(":") {
-            None => ipv6,
-            Some(pos) => &ipv6[pos+1..],
-        };
-        return Some((ipv4_to_u32(ipv4)? as u128) | 0xffff_0000_0000);
+fn parse_groups(text: &str) -> Result<Vec<&str>, AddrError> {
+    if text.is_empty() {
+        return Ok(vec![]);
     }
-    let mut split = ipv6.split([':'].as_ref()).collect::<Vec<&str>>();
-    loop {
-        let blank_pos = match split.iter().position(|&x| x == "") {
-            None => break,
-            Some(pos) => pos,
+    let groups = text.split(':').collect::<Vec<_>>();
+    if groups.iter().any(|x| x.is_empty()) {
+        return Err(AddrError::EmptyGroup);
+    }
+    Ok(groups)
+}
+
+pub fn parse_ipv6(text: &str, mode: ParseMode) -> Result<u128, AddrError> {
+    let mut text = text;
+    if mode == ParseMode::Lenient {
+        text = text.trim();
+        text = text.strip_prefix('[').and_then(|x| x.strip_suffix(']')).unwrap_or(text);
+        text = text.split_once('%').map_or(text, |x| x.0);
+        if !text.contains(':') && text.contains('.') {
+            return Ok(parse_ipv4(text, mode)? as u128 | MAPPED);
+        }
+    }
+    if text.is_empty() {
+        return Err(AddrError::Empty);
+    }
+    let parts = text.split("::").collect::<Vec<_>>();
+    if parts.len() > 2 {
+        return Err(AddrError::MultipleDoubleColon);
+    }
+    let head = parse_groups(parts[0])?;
+    let tail = match parts.get(1) {
+        Some(x) => parse_groups(x)?,
+        None => vec![],
+    };
+    let all = head.iter().chain(&tail).collect::<Vec<_>>();
+    // 以 :: 结尾时最后一组后面还有 0
+    let last = if parts.len() == 2 && tail.is_empty() { None } else { all.len().checked_sub(1) };
+    if let Some((_, x)) = all.iter().enumerate().find(|&(i, x)| x.contains('.') && Some(i) != last) {
+        return Err(AddrError::MisplacedIpv4(x.to_string()));
+    }
+    let mut values = vec![];
+    for (i, group) in all.iter().enumerate() {
+        if Some(i) == last && group.contains('.') {
+            let ipv4 = parse_ipv4(group, mode)?;
+            values.extend([(ipv4 >> 16) as u16, ipv4 as u16]);
+            continue;
+        }
+        if !group.bytes().all(|x| x.is_ascii_hexdigit()) {
+            return Err(AddrError::InvalidGroup(group.to_string()));
+        }
+        if group.len() > 4 {
+            return Err(AddrError::GroupTooLong(group.to_string()));
+        }
+        values.push(u16::from_str_radix(group, 16).map_err(|_| AddrError::InvalidGroup(group.to_string()))?);
+    }
+    // :: 至少代表一组 0
+    let count = values.len();
+    match parts.len() {
+        2 if count > 7 => return Err(AddrError::TooManyGroups(count)),
+        1 if count > 8 => return Err(AddrError::TooManyGroups(count)),
+        1 if count < 8 => return Err(AddrError::TooFewGroups(count)),
+        _ => {},
+    }
+    // head 放在前面，tail 放在后面，中间补 0
+    let head_len = head.iter().map(|x| if x.contains('.') { 2 } else { 1 }).sum::<usize>();
+    let mut groups = [0_u16; 8];
+    groups[..head_len].copy_from_slice(&values[..head_len]);
+    groups[8 - (count - head_len)..].copy_from_slice(&values[head_len..]);
+    Ok(groups.iter().fold(0, |x, &y| x << 16 | y as u128))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Radix {
+    Decimal,
+    // 0 开头
+    Octal,
+    // 0x 开头
+    Hex,
+}
+
+// inet_aton 的写法：1 到 4 部分，每部分可以是十进制、八进制或十六进制
+// 少于 4 部分时最后一部分表示剩下的所有字节，例如 127.1 的 1 占 24 位，单独一部分就是 32 位整数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ipv4Notation {
+    pub radixes: Vec<Radix>,
+}
+
+impl Ipv4Notation {
+    const SHAPES: [&'static str; 4] = ["integer", "two-part", "three-part", "dotted"];
+
+    // 例如 dotted-decimal、two-part-hex、integer-octal，同时出现八进制和十六进制时为 mixed
+    pub fn name(&self) -> String {
+        let shape = Ipv4Notation::SHAPES[self.radixes.len().clamp(1, 4) - 1];
+        let radix = match (self.radixes.contains(&Radix::Hex), self.radixes.contains(&Radix::Octal)) {
+            (true, true) => "mixed",
+            (true, false) => "hex",
+            (false, true) => "octal",
+            (false, false) => "decimal",
         };
-        let _ = std::mem::replace(&mut split[blank_pos], "0");
-        while split.len() != 8 {
-            split.insert(blank_pos, "0");
+        format!("{}-{}", shape, radix)
+    }
+}
+
+fn parse_aton_part(part: &str) -> Result<(u64, Radix), AddrError> {
+    let invalid = || AddrError::InvalidPart(part.to_string());
+    let (digits, radix) = if let Some(x) = part.strip_prefix("0x").or_else(|| part.strip_prefix("0X")) {
+        (x, Radix::Hex)
+    } else if part.len() > 1 && part.starts_with('0') {
+        (&part[1..], Radix::Octal)
+    } else {
+        (part, Radix::Decimal)
+    };
+    let base = match radix {
+        Radix::Decimal => 10,
+        Radix::Octal => 8,
+        Radix::Hex => 16,
+    };
+    // 与 glibc 相同，单独的 0x 表示 0
+    if digits.is_empty() {
+        return Ok((0, radix));
+    }
+    if !digits.chars().all(|x| x.is_digit(base)) {
+        return Err(invalid());
+    }
+    let value = u64::from_str_radix(digits.trim_start_matches('0'), base).or_else(|_| {
+        if digits.trim_start_matches('0').is_empty() { Ok(0) } else { Err(AddrError::PartOutOfRange(part.to_string())) }
+    })?;
+    Ok((value, radix))
+}
+
+// inet_aton 接受的所有写法：0x7f.1、0177.0.0.1、2130706433、127.1 等，返回地址和使用的写法
+pub fn parse_ipv4_aton(text: &str) -> Result<(u32, Ipv4Notation), AddrError> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(AddrError::Empty);
+    }
+    let parts = text.split('.').collect::<Vec<_>>();
+    if parts.len() > 4 {
+        return Err(AddrError::PartCount(parts.len()));
+    }
+    let mut number = 0_u64;
+    let mut radixes = vec![];
+    for (i, part) in parts.iter().enumerate() {
+        if part.is_empty() {
+            return Err(AddrError::EmptyPart(i + 1));
+        }
+        let (value, radix) = parse_aton_part(part)?;
+        // 最后一部分占剩下的字节，前面的每部分一个字节
+        let bits = if i + 1 == parts.len() { 8 * (4 - i) } else { 8 };
+        if value >> bits != 0 {
+            return Err(AddrError::PartOutOfRange(part.to_string()));
         }
+        number = number << bits | value;
+        radixes.push(radix);
     }
-    let mut number: u128 = match u128::from_str_radix(split.get(0)?, 16) {
-        Err(_) => None,
-        Ok(x) => Some(x),
-    }?;
-    for i in 1..8 {
-        number = number << 16;
-        number = number | match u128::from_str_radix(split.get(i)?, 16) {
-            Err(_) => None,
-            Ok(x) => Some(x),
-        }?;
+    Ok((number as u32, Ipv4Notation { radixes }))
+}
+
+// 按 inet_aton 的写法输出，parts 为 1 到 4 部分，所有部分使用同一个进制
+pub fn format_ipv4_aton(number: u32, parts: usize, radix: Radix) -> String {
+    let parts = parts.clamp(1, 4);
+    let mut values = vec![];
+    for i in 0..parts - 1 {
+        values.push(number >> (24 - 8 * i) & 0xff);
+    }
+    values.push(number & (u32::MAX >> (8 * (parts - 1))));
+    values.into_iter().map(|x| match radix {
+        Radix::Decimal => x.to_string(),
+        Radix::Octal => format!("0{:o}", x),
+        Radix::Hex => format!("0x{:x}", x),
+    }).collect::<Vec<_>>().join(".")
+}
+
+// 地址在所有写法下的文本，dotted-decimal 在最前面
+pub fn ipv4_notations(number: u32) -> Vec<(String, String)> {
+    let mut result = vec![];
+    for parts in (1..=4).rev() {
+        for radix in [Radix::Decimal, Radix::Octal, Radix::Hex] {
+            let name = Ipv4Notation { radixes: vec![radix; parts] }.name();
+            result.push((name, format_ipv4_aton(number, parts, radix)));
+        }
+    }
+    result
+}
+
+// convert 命令的结果：输入使用的写法、标准写法，以及所有写法下的文本
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conversion {
+    pub notation: String,
+    pub address: String,
+    pub notations: Vec<(String, String)>,
+}
+
+// 带 ':' 的按 IPv6 严格解析，其余按 inet_aton 的写法解析
+pub fn convert(text: &str) -> Result<Conversion, AddrError> {
+    if text.contains(':') {
+        let number = parse_ipv6(text, ParseMode::Strict)?;
+        let mut notations = [("canonical", Ipv6Style::Canonical), ("expanded", Ipv6Style::Expanded), ("padded", Ipv6Style::Padded)]
+            .map(|(name, style)| (name.to_string(), format_ipv6(number, style))).to_vec();
+        notations.push(("integer".to_string(), number.to_string()));
+        return Ok(Conversion { notation: "ipv6".to_string(), address: u128_to_ipv6(number), notations });
     }
-    Some(number)
+    let (number, notation) = parse_ipv4_aton(text)?;
+    Ok(Conversion { notation: notation.name(), address: u32_to_ipv4(number), notations: ipv4_notations(number) })
+}
+
+#[allow(dead_code)]
+pub fn ipv4_to_u32(ipv4: &str) -> Option<u32> {
+    parse_ipv4(ipv4, ParseMode::Strict).ok()
+}
+
+#[allow(dead_code)]
+pub fn ipv6_to_u128(ipv6: &str) -> Option<u128> {
+    parse_ipv6(ipv6, ParseMode::Strict).ok()
 }
 
 #[allow(dead_code)]
@@ -62,29 +315,68 @@ pub fn u32_to_ipv4(mut number: u32) -> String {
     arr.join(".")
 }
 
-#[allow(dead_code)]
-pub fn u128_to_ipv6(mut number: u128) -> String {
-    // if number >= 0xffff_0000_0000 && number <= 0xffff_ffff_ffff {
-    //     return u32_to_ipv4((number & 0xffff_ffff).try_into().unwrap());
-    // }
-    let mut arr: [String; 8] = Default::default();
-    for i in (0..8).rev() {
-        arr[i] = format!("{:x}", (number & 0xffff));
-        number = number >> 16;
+// IPv6 地址的文本格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Ipv6Style {
+    // RFC 5952：小写，最长的一段连续 0（至少两组，长度相同时取第一段）压缩为 ::，::ffff:0:0/96 写成 ::ffff:1.2.3.4
+    #[default]
+    Canonical,
+    // 8 组都写出来，不压缩：2001:200:0:0:0:0:0:0
+    Expanded,
+    // 8 组都补足 4 位：2001:0200:0000:0000:0000:0000:0000:0000
+    Padded,
+}
+
+impl std::str::FromStr for Ipv6Style {
+    type Err = String;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        match str {
+            "canonical" => Ok(Ipv6Style::Canonical),
+            "expanded" => Ok(Ipv6Style::Expanded),
+            "padded" => Ok(Ipv6Style::Padded),
+            x => Err(format!("unknown ipv6 style {}", x)),
+        }
+    }
+}
+
+pub fn format_ipv6(number: u128, style: Ipv6Style) -> String {
+    let groups: [u16; 8] = std::array::from_fn(|i| (number >> (112 - 16 * i)) as u16);
+    match style {
+        Ipv6Style::Expanded => return groups.map(|x| format!("{:x}", x)).join(":"),
+        Ipv6Style::Padded => return groups.map(|x| format!("{:04x}", x)).join(":"),
+        Ipv6Style::Canonical => {},
+    }
+    if number >> 32 == 0xffff {
+        return format!("::ffff:{}", u32_to_ipv4(number as u32));
+    }
+    // 最长的一段连续 0
+    let (mut best, mut current) = ((0, 0), (0, 0));
+    for (i, &x) in groups.iter().enumerate() {
+        if x != 0 {
+            continue;
+        }
+        current = if current.1 > 0 && current.0 + current.1 == i { (current.0, current.1 + 1) } else { (i, 1) };
+        if current.1 > best.1 {
+            best = current;
+        }
     }
-    arr.join(":")
+    let join = |list: &[u16]| list.iter().map(|x| format!("{:x}", x)).collect::<Vec<_>>().join(":");
+    if best.1 < 2 {
+        return join(&groups);
+    }
+    format!("{}::{}", join(&groups[..best.0]), join(&groups[best.0 + best.1..]))
+}
+
+#[allow(dead_code)]
+pub fn u128_to_ipv6(number: u128) -> String {
+    format_ipv6(number, Ipv6Style::Canonical)
 }
 
 #[allow(dead_code)]
 pub fn ipv4_to_ipv6(ipv4: &str) -> Option<String> {
     let number = ipv4_to_u32(ipv4)?;
-    let mut number = number as u128 | 0xffff_0000_0000;
-    let mut arr: [String; 8] = Default::default();
-    for i in (0..8).rev() {
-        arr[i] = format!("{:x}", (number & 0xffff));
-        number = number >> 16;
-    }
-    Some(arr.join(":"))
+    Some(u128_to_ipv6(number as u128 | MAPPED))
 }
 
 #[allow(dead_code)]
@@ -93,14 +385,24 @@ pub fn ipv6_to_ipv4(ipv6: &str) -> Option<String> {
     Some(u32_to_ipv4((number & 0xffff_ffff) as u32))
 }
 
+// 地址在数据集中对应的数字，IPv6 数据集中 IPv4 地址放在 ::ffff:0:0/96，IPv4 数据集只能表示 IPv4 地址
+pub fn ip_to_number(ip: std::net::IpAddr, ipv6: bool) -> Option<u128> {
+    match ip {
+        std::net::IpAddr::V4(x) if ipv6 => Some(u32::from(x) as u128 | MAPPED),
+        std::net::IpAddr::V4(x) => Some(u32::from(x) as u128),
+        std::net::IpAddr::V6(x) if ipv6 => Some(u128::from(x)),
+        std::net::IpAddr::V6(x) => x.to_ipv4_mapped().map(|x| u32::from(x) as u128),
+    }
+}
+
 #[allow(dead_code)]
 #[derive(PartialEq, Debug)]
 pub struct CidrIpv4Info {
-    cidr: String,
-    ip_start: String,
-    ip_end: String,
-    mask: String,
-    count: u32,
+    pub cidr: String,
+    pub ip_start: String,
+    pub ip_end: String,
+    pub mask: String,
+    pub count: u32,
 }
 
 #[allow(dead_code)]
@@ -140,11 +442,11 @@ pub fn cidr_to_ipv4(cidr: &str) -> Option<CidrIpv4Info> {
 #[allow(dead_code)]
 #[derive(PartialEq, Debug)]
 pub struct CidrIpv6Info {
-    cidr: String,
-    ip_start: String,
-    ip_end: String,
-    mask: String,
-    count: u128,
+    pub cidr: String,
+    pub ip_start: String,
+    pub ip_end: String,
+    pub mask: String,
+    pub count: u128,
 }
 
 #[allow(dead_code)]
@@ -253,8 +555,246 @@ pub fn ipv6_to_cidr(ip_start: &str, ip_end: &str) -> Option<Vec<String>> {
     }
 }
 
+#[allow(dead_code)]
+pub fn cidr_to_range_ipv4(cidr: &str) -> Option<(u32, u32)> {
+    let (ip, prefix) = cidr.split_at(cidr.rfind('/')?);
+    let ip = ipv4_to_u32(ip)?;
+    let prefix = prefix[1..].parse::<u32>().ok()?;
+    if prefix > 32 {
+        return None;
+    }
+    let host_mask = u32::MAX.checked_shr(prefix).unwrap_or(0);
+    Some((ip & !host_mask, ip | host_mask))
+}
+
+#[allow(dead_code)]
+pub fn cidr_to_range_ipv6(cidr: &str) -> Option<(u128, u128)> {
+    let (ip, prefix) = cidr.split_at(cidr.rfind('/')?);
+    let ip = ipv6_to_u128(ip)?;
+    let prefix = prefix[1..].parse::<u32>().ok()?;
+    if prefix > 128 {
+        return None;
+    }
+    let host_mask = u128::MAX.checked_shr(prefix).unwrap_or(0);
+    Some((ip & !host_mask, ip | host_mask))
+}
+
+// IANA IPv4/IPv6 Special-Purpose Address Registry (RFC 6890 及后续更新)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SpecialPurpose {
+    ThisNetwork,
+    Private,
+    SharedAddress,
+    Loopback,
+    LinkLocal,
+    ProtocolAssignments,
+    Documentation,
+    Relay6to4,
+    Benchmarking,
+    Multicast,
+    Reserved,
+    Broadcast,
+    Unspecified,
+    Ipv4Mapped,
+    Translation,
+    DiscardOnly,
+    Teredo,
+    Orchid,
+    UniqueLocal,
+    As112,
+    Amt,
+    SegmentRouting,
+}
+
+impl SpecialPurpose {
+    // ::ffff:0:0/96 覆盖了全部 IPv4，IPv6 数据集里的 IPv4 段都在其中，所以 all 不包含它
+    pub const ALL: [SpecialPurpose; 21] = [
+        SpecialPurpose::ThisNetwork,
+        SpecialPurpose::Private,
+        SpecialPurpose::SharedAddress,
+        SpecialPurpose::Loopback,
+        SpecialPurpose::LinkLocal,
+        SpecialPurpose::ProtocolAssignments,
+        SpecialPurpose::Documentation,
+        SpecialPurpose::Relay6to4,
+        SpecialPurpose::Benchmarking,
+        SpecialPurpose::Multicast,
+        SpecialPurpose::Reserved,
+        SpecialPurpose::Broadcast,
+        SpecialPurpose::Unspecified,
+        SpecialPurpose::Translation,
+        SpecialPurpose::DiscardOnly,
+        SpecialPurpose::Teredo,
+        SpecialPurpose::Orchid,
+        SpecialPurpose::UniqueLocal,
+        SpecialPurpose::As112,
+        SpecialPurpose::Amt,
+        SpecialPurpose::SegmentRouting,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SpecialPurpose::ThisNetwork => "this-network",
+            SpecialPurpose::Private => "private",
+            SpecialPurpose::SharedAddress => "shared",
+            SpecialPurpose::Loopback => "loopback",
+            SpecialPurpose::LinkLocal => "link-local",
+            SpecialPurpose::ProtocolAssignments => "protocol-assignments",
+            SpecialPurpose::Documentation => "documentation",
+            SpecialPurpose::Relay6to4 => "6to4",
+            SpecialPurpose::Benchmarking => "benchmarking",
+            SpecialPurpose::Multicast => "multicast",
+            SpecialPurpose::Reserved => "reserved",
+            SpecialPurpose::Broadcast => "broadcast",
+            SpecialPurpose::Unspecified => "unspecified",
+            SpecialPurpose::Ipv4Mapped => "ipv4-mapped",
+            SpecialPurpose::Translation => "translation",
+            SpecialPurpose::DiscardOnly => "discard-only",
+            SpecialPurpose::Teredo => "teredo",
+            SpecialPurpose::Orchid => "orchid",
+            SpecialPurpose::UniqueLocal => "unique-local",
+            SpecialPurpose::As112 => "as112",
+            SpecialPurpose::Amt => "amt",
+            SpecialPurpose::SegmentRouting => "segment-routing",
+        }
+    }
+
+    // 逗号分隔的类别名，支持 all 和 none
+    pub fn parse_list(str: &str) -> Option<Vec<SpecialPurpose>> {
+        let mut list = vec![];
+        for name in str.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+            match name {
+                "all" => list.extend(SpecialPurpose::ALL),
+                "none" => {},
+                _ => list.push(name.parse().ok()?),
+            }
+        }
+        Some(list)
+    }
+}
+
+impl std::str::FromStr for SpecialPurpose {
+    type Err = String;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        SpecialPurpose::ALL.iter().chain([SpecialPurpose::Ipv4Mapped].iter())
+            .find(|x| x.name() == str)
+            .copied()
+            .ok_or_else(|| format!("unknown special-purpose category: {}", str))
+    }
+}
+
+impl std::fmt::Display for SpecialPurpose {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+const SPECIAL_PURPOSE_IPV4: [(&str, SpecialPurpose); 24] = [
+    ("0.0.0.0/8", SpecialPurpose::ThisNetwork),
+    ("10.0.0.0/8", SpecialPurpose::Private),
+    ("100.64.0.0/10", SpecialPurpose::SharedAddress),
+    ("127.0.0.0/8", SpecialPurpose::Loopback),
+    ("169.254.0.0/16", SpecialPurpose::LinkLocal),
+    ("172.16.0.0/12", SpecialPurpose::Private),
+    ("192.0.0.0/24", SpecialPurpose::ProtocolAssignments),
+    ("192.0.2.0/24", SpecialPurpose::Documentation),
+    ("192.31.196.0/24", SpecialPurpose::As112),
+    ("192.52.193.0/24", SpecialPurpose::Amt),
+    ("192.88.99.0/24", SpecialPurpose::Relay6to4),
+    ("192.168.0.0/16", SpecialPurpose::Private),
+    ("192.175.48.0/24", SpecialPurpose::As112),
+    ("198.18.0.0/15", SpecialPurpose::Benchmarking),
+    ("198.51.100.0/24", SpecialPurpose::Documentation),
+    ("203.0.113.0/24", SpecialPurpose::Documentation),
+    ("224.0.0.0/4", SpecialPurpose::Multicast),
+    ("233.252.0.0/24", SpecialPurpose::Documentation),
+    ("240.0.0.0/4", SpecialPurpose::Reserved),
+    ("255.255.255.255/32", SpecialPurpose::Broadcast),
+    // 下面几段在上面的大段之内，按最长前缀匹配时优先
+    ("192.0.0.0/29", SpecialPurpose::ProtocolAssignments),
+    ("192.0.0.8/32", SpecialPurpose::ProtocolAssignments),
+    ("192.0.0.170/31", SpecialPurpose::Translation),
+    ("192.0.0.9/32", SpecialPurpose::ProtocolAssignments),
+];
+
+const SPECIAL_PURPOSE_IPV6: [(&str, SpecialPurpose); 21] = [
+    ("::/128", SpecialPurpose::Unspecified),
+    ("::1/128", SpecialPurpose::Loopback),
+    ("::ffff:0:0/96", SpecialPurpose::Ipv4Mapped),
+    ("64:ff9b::/96", SpecialPurpose::Translation),
+    ("64:ff9b:1::/48", SpecialPurpose::Translation),
+    ("100::/64", SpecialPurpose::DiscardOnly),
+    ("2001::/23", SpecialPurpose::ProtocolAssignments),
+    ("2001::/32", SpecialPurpose::Teredo),
+    ("2001:2::/48", SpecialPurpose::Benchmarking),
+    ("2001:3::/32", SpecialPurpose::Amt),
+    ("2001:4:112::/48", SpecialPurpose::As112),
+    ("2001:10::/28", SpecialPurpose::Orchid),
+    ("2001:20::/28", SpecialPurpose::Orchid),
+    ("2001:db8::/32", SpecialPurpose::Documentation),
+    ("2002::/16", SpecialPurpose::Relay6to4),
+    ("2620:4f:8000::/48", SpecialPurpose::As112),
+    ("3fff::/20", SpecialPurpose::Documentation),
+    ("5f00::/16", SpecialPurpose::SegmentRouting),
+    ("fc00::/7", SpecialPurpose::UniqueLocal),
+    ("fe80::/10", SpecialPurpose::LinkLocal),
+    ("ff00::/8", SpecialPurpose::Multicast),
+];
+
+fn special_purpose_ipv6_table() -> impl Iterator<Item = (u128, u128, SpecialPurpose)> {
+    SPECIAL_PURPOSE_IPV6.into_iter()
+        .map(|(cidr, category)| {
+            let (start, end) = cidr_to_range_ipv6(cidr).unwrap();
+            (start, end, category)
+        })
+}
+
+#[allow(dead_code)]
+pub fn special_purpose_ipv4(ip: u32) -> Option<SpecialPurpose> {
+    SPECIAL_PURPOSE_IPV4.into_iter()
+        .map(|(cidr, category)| (cidr_to_range_ipv4(cidr).unwrap(), category))
+        .filter(|&((start, end), _)| start <= ip && ip <= end)
+        .min_by_key(|&((start, end), _)| end - start)
+        .map(|(_, category)| category)
+}
+
+// IPv4 映射地址按其中的 IPv4 地址分类，不属于任何特殊用途时才归为 ipv4-mapped
+#[allow(dead_code)]
+pub fn special_purpose_ipv6(ip: u128) -> Option<SpecialPurpose> {
+    if (MAPPED..=MAPPED_END).contains(&ip) {
+        return special_purpose_ipv4(ip as u32).or(Some(SpecialPurpose::Ipv4Mapped));
+    }
+    special_purpose_ipv6_table()
+        .filter(|&(start, end, _)| start <= ip && ip <= end)
+        .min_by_key(|&(start, end, _)| end - start)
+        .map(|(_, _, category)| category)
+}
+
+#[allow(dead_code)]
+pub fn special_purpose_ranges_ipv4(categories: &[SpecialPurpose]) -> Vec<(u32, u32)> {
+    SPECIAL_PURPOSE_IPV4.into_iter()
+        .filter(|(_, category)| categories.contains(category))
+        .map(|(cidr, _)| cidr_to_range_ipv4(cidr).unwrap())
+        .collect()
+}
+
+// 包含映射到 ::ffff:0:0/96 中的 IPv4 特殊用途地址
+#[allow(dead_code)]
+pub fn special_purpose_ranges_ipv6(categories: &[SpecialPurpose]) -> Vec<(u128, u128)> {
+    let mapped = special_purpose_ranges_ipv4(categories).into_iter()
+        .map(|(start, end)| (start as u128 | MAPPED, end as u128 | MAPPED));
+    special_purpose_ipv6_table()
+        .filter(|(_, _, category)| categories.contains(category))
+        .map(|(start, end, _)| (start, end))
+        .chain(mapped)
+        .collect()
+}
+
 #[cfg(test)]
 mod ip_tool_test {
+    use proptest::prelude::*;
+
     use crate::ip_tool::*;
 
     #[test]
@@ -303,26 +843,152 @@ mod ip_tool_test {
             panic!("convert ::1 to u128 fail")
         }
         assert_eq!(1, x.unwrap());
-        let x = ipv6_to_u128("255.255.255.255");
-        if x == None {
-            panic!("convert 255.255.255.255 to u128 fail")
+        // 单独的 IPv4 地址只在宽松模式下当作映射地址
+        assert_eq!(None, ipv6_to_u128("255.255.255.255"));
+        assert_eq!(Ok(281474976710655), parse_ipv6("255.255.255.255", ParseMode::Lenient));
+        assert_eq!(Ok(281470681743360), parse_ipv6("0.0.0.0", ParseMode::Lenient));
+        assert_eq!(Ok(281474439839743), parse_ipv6("223.255.255.255", ParseMode::Lenient));
+        // 点分的部分就是最后 32 位，不会变成映射地址
+        assert_eq!(Some(0), ipv6_to_u128("::0.0.0.0"));
+        assert_eq!(Some(281474439839743), ipv6_to_u128("::ffff:223.255.255.255"));
+    }
+
+    #[test]
+    fn parse_ipv4_test() {
+        let strict = |x: &str| parse_ipv4(x, ParseMode::Strict);
+        assert_eq!(Ok(0x0102_0304), strict("1.2.3.4"));
+        assert_eq!(Err(AddrError::OctetOutOfRange("256".to_string())), strict("256.1.1.1"));
+        assert_eq!(Err(AddrError::InvalidOctet("+1".to_string())), strict("+1.2.3.4"));
+        assert_eq!(Err(AddrError::LeadingZero("01".to_string())), strict("01.2.3.4"));
+        assert_eq!(Err(AddrError::OctetCount(3)), strict("1.2.3"));
+        assert_eq!(Err(AddrError::EmptyOctet(4)), strict("1.2.3."));
+        assert_eq!(Err(AddrError::Empty), strict(""));
+        assert_eq!(Err(AddrError::InvalidOctet(" 1".to_string())), strict(" 1.2.3.4"));
+        assert_eq!("octet '256' is greater than 255", strict("256.1.1.1").unwrap_err().to_string());
+        let lenient = |x: &str| parse_ipv4(x, ParseMode::Lenient);
+        assert_eq!(Ok(0x0102_0304), lenient(" 001.02.3.4 "));
+        assert_eq!(Err(AddrError::OctetOutOfRange("0256".to_string())), lenient("0256.1.1.1"));
+        assert_eq!(Err(AddrError::InvalidOctet("+1".to_string())), lenient("+1.2.3.4"));
+    }
+
+    #[test]
+    fn parse_ipv6_test() {
+        let strict = |x: &str| parse_ipv6(x, ParseMode::Strict);
+        assert_eq!(Ok(0), strict("::"));
+        assert_eq!(Ok(1 << 112), strict("1::"));
+        assert_eq!(Ok(0x0001_0000_0000_0000_0000_0000_0000_0002), strict("1::2"));
+        assert_eq!(Ok(0xffff_0102_0304), strict("::ffff:1.2.3.4"));
+        assert_eq!(Ok(0x0064_ff9b_0000_0000_0000_0000_0102_0304), strict("64:ff9b::1.2.3.4"));
+        assert_eq!(Ok(0x0001_0002_0003_0004_0005_0006_0007_0000), strict("1:2:3:4:5:6:7::"));
+        assert_eq!(Err(AddrError::MultipleDoubleColon), strict("1::2::3"));
+        assert_eq!(Err(AddrError::TooManyGroups(9)), strict("1:2:3:4:5:6:7:8:9"));
+        assert_eq!(Err(AddrError::TooManyGroups(8)), strict("1:2:3:4:5:6:7::8"));
+        assert_eq!(Err(AddrError::TooFewGroups(7)), strict("1:2:3:4:5:6:7"));
+        assert_eq!(Err(AddrError::GroupTooLong("12345".to_string())), strict("12345::"));
+        assert_eq!(Err(AddrError::InvalidGroup("g".to_string())), strict("g::"));
+        assert_eq!(Err(AddrError::MisplacedIpv4("1.2.3.4".to_string())), strict("1.2.3.4::"));
+        assert_eq!(Err(AddrError::MisplacedIpv4("1.2.3.4".to_string())), strict("::1.2.3.4:1"));
+        assert_eq!(Err(AddrError::OctetOutOfRange("256".to_string())), strict("::ffff:1.2.3.256"));
+        assert_eq!(Err(AddrError::EmptyGroup), strict(":1::"));
+        assert_eq!(Err(AddrError::EmptyGroup), strict(":::"));
+        assert_eq!(Err(AddrError::TooFewGroups(2)), strict("1.2.3.4"));
+        assert_eq!(Err(AddrError::InvalidGroup("[".to_string())), strict("[::1]"));
+        let lenient = |x: &str| parse_ipv6(x, ParseMode::Lenient);
+        assert_eq!(Ok(1), lenient(" [::1] "));
+        assert_eq!(Ok(0xfe80 << 112 | 1), lenient("fe80::1%eth0"));
+        assert_eq!(Ok(0xffff_0102_0304), lenient("1.2.3.4"));
+        assert_eq!(Err(AddrError::MultipleDoubleColon), lenient("1::2::3"));
+    }
+
+    #[test]
+    fn parse_ipv4_aton_test() {
+        let parse = |x: &str| parse_ipv4_aton(x).map(|(number, notation)| (u32_to_ipv4(number), notation.name()));
+        let ok = |x: &str, y: &str| Ok((x.to_string(), y.to_string()));
+        assert_eq!(ok("127.0.0.1", "dotted-decimal"), parse("127.0.0.1"));
+        assert_eq!(ok("127.0.0.1", "two-part-hex"), parse("0x7f.1"));
+        assert_eq!(ok("127.0.0.1", "dotted-octal"), parse("0177.0.0.1"));
+        assert_eq!(ok("127.0.0.1", "integer-decimal"), parse("2130706433"));
+        assert_eq!(ok("127.0.0.1", "two-part-decimal"), parse("127.1"));
+        assert_eq!(ok("127.0.1.1", "three-part-decimal"), parse("127.0.257"));
+        assert_eq!(ok("127.0.0.1", "integer-hex"), parse("0X7F000001"));
+        assert_eq!(ok("127.0.0.1", "integer-octal"), parse("017700000001"));
+        assert_eq!(ok("192.168.0.1", "dotted-mixed"), parse("0xc0.0250.0.1"));
+        assert_eq!(ok("0.0.0.0", "integer-hex"), parse("0x"));
+        assert_eq!(Err(AddrError::PartOutOfRange("256".to_string())), parse("256.1.1.1"));
+        assert_eq!(Err(AddrError::PartOutOfRange("16777216".to_string())), parse("1.16777216"));
+        assert_eq!(Err(AddrError::PartOutOfRange("4294967296".to_string())), parse("4294967296"));
+        assert_eq!(Err(AddrError::PartOutOfRange("99999999999999999999999".to_string())), parse("99999999999999999999999"));
+        assert_eq!(Err(AddrError::InvalidPart("08".to_string())), parse("08.0.0.1"));
+        assert_eq!(Err(AddrError::InvalidPart("+1".to_string())), parse("+1.2.3.4"));
+        assert_eq!(Err(AddrError::InvalidPart("0xg".to_string())), parse("0xg"));
+        assert_eq!(Err(AddrError::PartCount(5)), parse("1.2.3.4.5"));
+        assert_eq!(Err(AddrError::EmptyPart(2)), parse("1..2"));
+        assert_eq!("part '08' is not a decimal, octal or hex number", parse("08").unwrap_err().to_string());
+    }
+
+    #[test]
+    fn format_ipv4_aton_test() {
+        let number = 0x7f00_0001;
+        assert_eq!("0x7f.0x1", format_ipv4_aton(number, 2, Radix::Hex));
+        assert_eq!("0177.00.00.01", format_ipv4_aton(number, 4, Radix::Octal));
+        assert_eq!("127.0.1", format_ipv4_aton(number, 3, Radix::Decimal));
+        assert_eq!("2130706433", format_ipv4_aton(number, 1, Radix::Decimal));
+        let notations = ipv4_notations(number);
+        assert_eq!(12, notations.len());
+        assert_eq!(("dotted-decimal".to_string(), "127.0.0.1".to_string()), notations[0]);
+        assert!(notations.contains(&("integer-hex".to_string(), "0x7f000001".to_string())));
+        let conversion = convert("0x7f.1").unwrap();
+        assert_eq!(("two-part-hex", "127.0.0.1"), (conversion.notation.as_str(), conversion.address.as_str()));
+        let conversion = convert("::ffff:7f00:1").unwrap();
+        assert_eq!("::ffff:127.0.0.1", conversion.address);
+        assert!(conversion.notations.contains(&("expanded".to_string(), "0:0:0:0:0:ffff:7f00:1".to_string())));
+        assert_eq!(Err(AddrError::MultipleDoubleColon), convert("1::2::3"));
+    }
+
+    proptest! {
+        // 每种写法输出的文本都能解析回同一个地址，写法也相同
+        #[test]
+        fn ipv4_notations_test(number: u32) {
+            for (name, text) in ipv4_notations(number) {
+                let (parsed, notation) = parse_ipv4_aton(&text).unwrap();
+                prop_assert_eq!(number, parsed);
+                prop_assert_eq!(name, notation.name());
+            }
         }
-        assert_eq!(281474976710655, x.unwrap());
-        let x = ipv6_to_u128("0.0.0.0");
-        if x == None {
-            panic!("convert 0.0.0.0 to u128 fail")
+
+        // 点分十进制与 std::net 的结果相同
+        #[test]
+        fn parse_ipv4_aton_std_test(number: u32) {
+            let text = std::net::Ipv4Addr::from(number).to_string();
+            prop_assert_eq!(Ok(number), parse_ipv4_aton(&text).map(|x| x.0));
         }
-        assert_eq!(281470681743360, x.unwrap());
-        let x = ipv6_to_u128("::0.0.0.0");
-        if x == None {
-            panic!("convert ::0.0.0.0 to u128 fail")
+
+        // 严格模式与 std::net 接受同样的写法，结果相同
+        #[test]
+        fn parse_ipv4_std_test(text in "[0-9+.]{0,16}|(0?[0-9]{1,3}\\.){3}0?[0-9]{1,3}") {
+            let std = text.parse::<std::net::Ipv4Addr>().map(u32::from).ok();
+            prop_assert_eq!(std, parse_ipv4(&text, ParseMode::Strict).ok());
         }
-        assert_eq!(281470681743360, x.unwrap());
-        let x = ipv6_to_u128("223.255.255.255");
-        if x == None {
-            panic!("convert 223.255.255.255 to u128 fail")
+
+        #[test]
+        fn parse_ipv6_std_test(groups in proptest::collection::vec(prop_oneof![
+            Just(""), Just("0"), Just("1"), Just("ffff"), Just("0Ab1"), Just("12345"), Just("g"), Just("1.2.3.4"), Just("01.2.3.4"), Just("256.0.0.1"),
+        ], 0..11)) {
+            // 由空组和各种组拼出来，包括 ::、:::、多个 :: 和位置不对的 IPv4
+            let text = groups.join(":");
+            let std = text.parse::<std::net::Ipv6Addr>().map(u128::from).ok();
+            prop_assert_eq!(std, parse_ipv6(&text, ParseMode::Strict).ok(), "{}", text);
+        }
+
+        #[test]
+        fn parse_ipv6_format_test(number: u128) {
+            for style in [Ipv6Style::Canonical, Ipv6Style::Expanded, Ipv6Style::Padded] {
+                prop_assert_eq!(Ok(number), parse_ipv6(&format_ipv6(number, style), ParseMode::Strict));
+            }
+            let text = std::net::Ipv6Addr::from(number).to_string();
+            prop_assert_eq!(Ok(number), parse_ipv6(&text, ParseMode::Strict));
+            prop_assert_eq!(Ok(number), parse_ipv6(&format!("[{}]", text), ParseMode::Lenient));
         }
-        assert_eq!(281474439839743, x.unwrap());
     }
     
     #[test]
@@ -336,19 +1002,49 @@ mod ip_tool_test {
     #[test]
     fn u128_to_ipv6_test() {
         assert_eq!("ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff", u128_to_ipv6(340282366920938463463374607431768211455));
-        assert_eq!("2001:200:0:0:0:0:0:0", u128_to_ipv6(42540528726795050063891204319802818560));
-        assert_eq!("0:0:0:0:0:0:0:1", u128_to_ipv6(1));
-        assert_eq!("0:0:0:0:0:0:0:0", u128_to_ipv6(0));
-        // assert_eq!("0.0.0.0", u128_to_ipv6(281470681743360));
-        // assert_eq!("223.255.255.255", u128_to_ipv6(281474439839743));
-        // assert_eq!("255.255.255.255", u128_to_ipv6(281474976710655));
+        assert_eq!("2001:200::", u128_to_ipv6(42540528726795050063891204319802818560));
+        assert_eq!("::1", u128_to_ipv6(1));
+        assert_eq!("::", u128_to_ipv6(0));
+        assert_eq!("::ffff:0.0.0.0", u128_to_ipv6(281470681743360));
+        assert_eq!("::ffff:223.255.255.255", u128_to_ipv6(281474439839743));
+        assert_eq!("::ffff:255.255.255.255", u128_to_ipv6(281474976710655));
+    }
+
+    #[test]
+    fn format_ipv6_test() {
+        let number = |x: &str| x.parse::<std::net::Ipv6Addr>().map(u128::from).unwrap();
+        // 只有一组 0 时不压缩，长度相同时压缩第一段
+        assert_eq!("2001:db8:0:1:1:1:1:1", u128_to_ipv6(number("2001:db8:0:1:1:1:1:1")));
+        assert_eq!("2001:db8::1:0:0:1", u128_to_ipv6(number("2001:db8:0:0:1:0:0:1")));
+        assert_eq!("2001:0:0:1::1", u128_to_ipv6(number("2001:0:0:1:0:0:0:1")));
+        assert_eq!("1::", u128_to_ipv6(number("1:0:0:0:0:0:0:0")));
+        assert_eq!("::ffff:1.2.3.4", u128_to_ipv6(number("::ffff:102:304")));
+        // 只有 ::ffff:0:0/96 写成点分形式
+        assert_eq!("::102:304", u128_to_ipv6(number("::1.2.3.4")));
+        assert_eq!("64:ff9b::102:304", u128_to_ipv6(number("64:ff9b::1.2.3.4")));
+        assert_eq!("2001:200:0:0:0:0:0:0", format_ipv6(number("2001:200::"), Ipv6Style::Expanded));
+        assert_eq!("0:0:0:0:0:ffff:102:304", format_ipv6(number("::ffff:1.2.3.4"), Ipv6Style::Expanded));
+        assert_eq!("2001:0200:0000:0000:0000:0000:0000:00ab", format_ipv6(number("2001:200::ab"), Ipv6Style::Padded));
+        assert_eq!(Ok(Ipv6Style::Padded), "padded".parse());
+        assert!("short".parse::<Ipv6Style>().is_err());
+    }
+
+    proptest! {
+        // 与标准库的格式一致，标准库把 ::ffff:0:0/96 同样写成点分形式
+        #[test]
+        fn format_ipv6_std_test(groups in proptest::array::uniform8(prop_oneof![Just(0_u16), Just(0xffff), any::<u16>()])) {
+            // 随机的 u128 几乎不会有连续的 0，按组生成
+            let number = groups.iter().fold(0_u128, |x, &y| x << 16 | y as u128);
+            prop_assert_eq!(std::net::Ipv6Addr::from(number).to_string(), u128_to_ipv6(number));
+            prop_assert_eq!(Some(number), ipv6_to_u128(&u128_to_ipv6(number)));
+        }
     }
     
     #[test]
     fn ipv4_to_ipv6_test() {
-        assert_eq!(Some("0:0:0:0:0:ffff:ffff:ffff".to_string()), ipv4_to_ipv6("255.255.255.255"));
-        assert_eq!(Some("0:0:0:0:0:ffff:3d80:8044".to_string()), ipv4_to_ipv6("61.128.128.68"));
-        assert_eq!(Some("0:0:0:0:0:ffff:0:0".to_string()), ipv4_to_ipv6("0.0.0.0"));
+        assert_eq!(Some("::ffff:255.255.255.255".to_string()), ipv4_to_ipv6("255.255.255.255"));
+        assert_eq!(Some("::ffff:61.128.128.68".to_string()), ipv4_to_ipv6("61.128.128.68"));
+        assert_eq!(Some("::ffff:0.0.0.0".to_string()), ipv4_to_ipv6("0.0.0.0"));
     }
     
     #[test]
@@ -394,22 +1090,22 @@ mod ip_tool_test {
     fn cidr_to_ipv6_test() {
         assert_eq!(CidrIpv6Info {
             cidr: "CDCD:910A:2222:5498:8475:1111:3900:2020/64".to_string(),
-            ip_start: "cdcd:910a:2222:5498:0:0:0:0".to_string(),
+            ip_start: "cdcd:910a:2222:5498::".to_string(),
             ip_end: "cdcd:910a:2222:5498:ffff:ffff:ffff:ffff".to_string(),
-            mask: "ffff:ffff:ffff:ffff:0:0:0:0".to_string(),
+            mask: "ffff:ffff:ffff:ffff::".to_string(),
             count: 2_u128.pow(64),
         }, cidr_to_ipv6("CDCD:910A:2222:5498:8475:1111:3900:2020/64").unwrap());
         assert_eq!(CidrIpv6Info {
             cidr: "::0/0".to_string(),
-            ip_start: "0:0:0:0:0:0:0:0".to_string(),
+            ip_start: "::".to_string(),
             ip_end: "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff".to_string(),
-            mask: "0:0:0:0:0:0:0:0".to_string(),
+            mask: "::".to_string(),
             count: 0, // 最大为 4294967296 ，u32 放不下，被挤到 0
         }, cidr_to_ipv6("::0/0").unwrap());
         assert_eq!(CidrIpv6Info {
             cidr: "::1/128".to_string(),
-            ip_start: "0:0:0:0:0:0:0:1".to_string(),
-            ip_end: "0:0:0:0:0:0:0:1".to_string(),
+            ip_start: "::1".to_string(),
+            ip_end: "::1".to_string(),
             mask: "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff".to_string(),
             count: 1,
         }, cidr_to_ipv6("::1/128").unwrap());
@@ -443,10 +1139,68 @@ mod ip_tool_test {
     #[test]
     fn ipv6_to_cidr_test() {
         assert_eq!(vec![
-            "16a0:10:ab00:1e:0:0:0:0/64".to_string(),
+            "16a0:10:ab00:1e::/64".to_string(),
         ], ipv6_to_cidr("16A0:0010:AB00:001E:0000:0000:0000:0000", "16A0:0010:AB00:001E:FFFF:FFFF:FFFF:FFFF").unwrap());
         assert_eq!(vec![
-            "0:0:0:0:0:0:0:0/0".to_string(),
+            "::/0".to_string(),
         ], ipv6_to_cidr("::", "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff").unwrap());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn cidr_to_range_test() {
+        assert_eq!(Some((0, u32::MAX)), cidr_to_range_ipv4("0.0.0.0/0"));
+        assert_eq!(Some((0x0a00_0000, 0x0aff_ffff)), cidr_to_range_ipv4("10.1.2.3/8"));
+        assert_eq!(Some((0x0102_0304, 0x0102_0304)), cidr_to_range_ipv4("1.2.3.4/32"));
+        assert_eq!(None, cidr_to_range_ipv4("1.2.3.4/33"));
+        assert_eq!(Some((0, u128::MAX)), cidr_to_range_ipv6("::/0"));
+        assert_eq!(Some((1, 1)), cidr_to_range_ipv6("::1/128"));
+        assert_eq!(Some((0xfe80 << 112, (0xfebf << 112) | 0xffff_ffff_ffff_ffff_ffff_ffff_ffff)), cidr_to_range_ipv6("fe80::/10"));
+        assert_eq!(None, cidr_to_range_ipv6("::/129"));
+    }
+
+    #[test]
+    fn special_purpose_ipv4_test() {
+        assert_eq!(Some(SpecialPurpose::Private), special_purpose_ipv4(ipv4_to_u32("192.168.1.1").unwrap()));
+        assert_eq!(Some(SpecialPurpose::Private), special_purpose_ipv4(ipv4_to_u32("172.31.255.255").unwrap()));
+        assert_eq!(None, special_purpose_ipv4(ipv4_to_u32("172.32.0.0").unwrap()));
+        assert_eq!(Some(SpecialPurpose::SharedAddress), special_purpose_ipv4(ipv4_to_u32("100.64.0.1").unwrap()));
+        assert_eq!(Some(SpecialPurpose::Loopback), special_purpose_ipv4(ipv4_to_u32("127.0.0.1").unwrap()));
+        assert_eq!(Some(SpecialPurpose::Documentation), special_purpose_ipv4(ipv4_to_u32("203.0.113.7").unwrap()));
+        assert_eq!(Some(SpecialPurpose::Translation), special_purpose_ipv4(ipv4_to_u32("192.0.0.171").unwrap()));
+        assert_eq!(Some(SpecialPurpose::Multicast), special_purpose_ipv4(ipv4_to_u32("239.1.1.1").unwrap()));
+        assert_eq!(Some(SpecialPurpose::Reserved), special_purpose_ipv4(ipv4_to_u32("250.0.0.1").unwrap()));
+        assert_eq!(Some(SpecialPurpose::Broadcast), special_purpose_ipv4(u32::MAX));
+        assert_eq!(None, special_purpose_ipv4(ipv4_to_u32("8.8.8.8").unwrap()));
+    }
+
+    #[test]
+    fn special_purpose_ipv6_test() {
+        assert_eq!(Some(SpecialPurpose::Unspecified), special_purpose_ipv6(0));
+        assert_eq!(Some(SpecialPurpose::Loopback), special_purpose_ipv6(1));
+        assert_eq!(Some(SpecialPurpose::Teredo), special_purpose_ipv6(ipv6_to_u128("2001:0:1::").unwrap()));
+        assert_eq!(Some(SpecialPurpose::ProtocolAssignments), special_purpose_ipv6(ipv6_to_u128("2001:1::1").unwrap()));
+        assert_eq!(Some(SpecialPurpose::Documentation), special_purpose_ipv6(ipv6_to_u128("2001:db8::1").unwrap()));
+        assert_eq!(Some(SpecialPurpose::UniqueLocal), special_purpose_ipv6(ipv6_to_u128("fd12:3456::1").unwrap()));
+        assert_eq!(Some(SpecialPurpose::Multicast), special_purpose_ipv6(ipv6_to_u128("ff02::1").unwrap()));
+        assert_eq!(Some(SpecialPurpose::Private), special_purpose_ipv6(ipv6_to_u128("::ffff:10.0.0.1").unwrap()));
+        assert_eq!(Some(SpecialPurpose::Ipv4Mapped), special_purpose_ipv6(ipv6_to_u128("::ffff:8.8.8.8").unwrap()));
+        assert_eq!(None, special_purpose_ipv6(ipv6_to_u128("2400:3200::1").unwrap()));
+    }
+
+    #[test]
+    fn special_purpose_parse_test() {
+        assert_eq!(Some(vec![SpecialPurpose::Private, SpecialPurpose::SharedAddress]), SpecialPurpose::parse_list("private, shared"));
+        assert_eq!(Some(SpecialPurpose::ALL.to_vec()), SpecialPurpose::parse_list("all"));
+        assert_eq!(Some(vec![]), SpecialPurpose::parse_list("none"));
+        assert_eq!(None, SpecialPurpose::parse_list("private,public"));
+        assert_eq!(Ok(SpecialPurpose::Ipv4Mapped), "ipv4-mapped".parse::<SpecialPurpose>());
+    }
+
+    #[test]
+    fn special_purpose_ranges_test() {
+        assert_eq!(vec![(0x0a00_0000, 0x0aff_ffff), (0xac10_0000, 0xac1f_ffff), (0xc0a8_0000, 0xc0a8_ffff)],
+            special_purpose_ranges_ipv4(&[SpecialPurpose::Private]));
+        assert_eq!(vec![(0xfc00 << 112, (0xfe00 << 112) - 1), (0xffff_0a00_0000, 0xffff_0aff_ffff), (0xffff_ac10_0000, 0xffff_ac1f_ffff), (0xffff_c0a8_0000, 0xffff_c0a8_ffff)],
+            special_purpose_ranges_ipv6(&[SpecialPurpose::Private, SpecialPurpose::UniqueLocal]));
+    }
+}
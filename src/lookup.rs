@@ -0,0 +1,103 @@
+use std::net::IpAddr;
+
+use crate::{error, extract::IpLocation, ip_tool};
+
+// 按起始地址排序的区间数组，二分查找 IP 所在的行
+pub struct IpIndex {
+    starts: Vec<u128>,
+    ends: Vec<u128>,
+    records: Vec<IpLocation>,
+    // IPv6 数据集中 IPv4 地址以 ::ffff:0:0/96 的形式存放
+    ipv6: bool,
+}
+
+impl IpIndex {
+    pub fn new(list: Vec<IpLocation>) -> error::Result<IpIndex> {
+        let mut rows = vec![];
+        for record in list {
+            let (start, end) = record.range()?;
+            rows.push((start, end, record));
+        }
+        rows.sort_by_key(|&(start, _, _)| start);
+        let ipv6 = rows.iter().any(|&(_, end, _)| end > u32::MAX as u128);
+        let mut starts = Vec::with_capacity(rows.len());
+        let mut ends = Vec::with_capacity(rows.len());
+        let mut records = Vec::with_capacity(rows.len());
+        for (start, end, record) in rows {
+            starts.push(start);
+            ends.push(end);
+            records.push(record);
+        }
+        Ok(IpIndex {
+            starts,
+            ends,
+            records,
+            ipv6,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> Option<&IpLocation> {
+        self.lookup_number(ip_tool::ip_to_number(ip, self.ipv6)?)
+    }
+
+    pub fn lookup_number(&self, number: u128) -> Option<&IpLocation> {
+        let index = self.starts.partition_point(|&start| start <= number).checked_sub(1)?;
+        if number > self.ends[index] {
+            return None;
+        }
+        self.records.get(index)
+    }
+}
+
+#[cfg(test)]
+mod lookup_test {
+    use std::net::IpAddr;
+
+    use crate::{extract::row, lookup::*};
+
+    fn ip(str: &str) -> IpAddr {
+        str.parse().unwrap()
+    }
+
+    #[test]
+    fn lookup_ipv4_test() {
+        let index = IpIndex::new(vec![
+            row(16777472, 16778239, "CN"),
+            row(16777216, 16777471, "US"),
+            row(16778240, 16779263, "AU"),
+        ]).unwrap();
+        assert_eq!(3, index.len());
+        assert_eq!("US", index.lookup(ip("1.0.0.0")).unwrap().country_code);
+        assert_eq!("US", index.lookup(ip("1.0.0.255")).unwrap().country_code);
+        assert_eq!("CN", index.lookup(ip("1.0.1.0")).unwrap().country_code);
+        assert_eq!("AU", index.lookup(ip("1.0.7.255")).unwrap().country_code);
+        assert!(index.lookup(ip("1.0.8.0")).is_none());
+        assert!(index.lookup(ip("0.255.255.255")).is_none());
+        assert_eq!("CN", index.lookup(ip("::ffff:1.0.1.1")).unwrap().country_code);
+        assert!(index.lookup(ip("2001:200::1")).is_none());
+    }
+
+    #[test]
+    fn lookup_ipv6_test() {
+        let index = IpIndex::new(vec![
+            row(0, 281470681743359, "-"),
+            row(281470698520576, 281470698520831, "US"),
+            row(281470698520832, 281470698521599, "CN"),
+            row(42540528726795050063891204319802818560, 42540528806023212578155541913346768895, "JP"),
+        ]).unwrap();
+        assert_eq!("US", index.lookup(ip("1.0.0.1")).unwrap().country_code);
+        assert_eq!("CN", index.lookup(ip("::ffff:1.0.1.1")).unwrap().country_code);
+        assert_eq!("JP", index.lookup(ip("2001:200::1")).unwrap().country_code);
+        assert_eq!("-", index.lookup(ip("::1")).unwrap().country_code);
+        assert!(index.lookup(ip("2001:100::1")).is_none());
+    }
+
+    #[test]
+    fn invalid_range_test() {
+        assert!(IpIndex::new(vec![row(10, 9, "US")]).is_err());
+    }
+}
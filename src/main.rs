@@ -1,152 +1,570 @@
-use std::{path::Path, env};
+use std::{collections::HashMap, fs, path::Path, env, net::IpAddr, io::{self, BufRead}, process::ExitCode, thread, time::Instant};
 
+use error::Error;
+use sha2::{Digest, Sha256};
+use param::{Args, Param};
+
+mod diff;
+mod domain;
+mod error;
 mod extract;
 mod ip_tool;
 mod file_tool;
+mod guard;
+mod lookup;
+mod map;
+mod overrides;
+mod param;
+mod profile;
+mod schedule;
+mod server;
+mod stats;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let param = param_analysis();
+fn main() -> ExitCode {
+    let args = Args::parse(env::args().skip(1));
+    let result = param::param_analysis(&args).and_then(|param| {
+        match args.command.as_deref() {
+            None | Some("extract") => main_extract(param),
+            Some("lookup") => main_lookup(&args, &param),
+            Some("serve") => main_serve(&args, &param),
+            Some("daemon") => main_daemon(&args, &param),
+            Some("rollback") => main_rollback(&param),
+            Some("diff") => main_diff(&args, &param),
+            Some("stats") => main_stats(&args, &param),
+            Some("map") => main_map(&args, &param),
+            Some("convert") => main_convert(&args),
+            Some(x) => Err(Error::Config(format!("unknown command: {}", x))),
+        }
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::from(e.exit_code())
+        },
+    }
+}
 
-    let url;
-    if param.ip2location_token == None {
-        url = "https://download.ip2location.com/lite/IP2LOCATION-LITE-DB1.CSV.ZIP".to_string();
-    } else {
-        url = format!("https://www.ip2location.com/download/?token={}&file=DB1LITECSV", param.ip2location_token.unwrap());
+fn main_extract(param: Param) -> error::Result<()> {
+    run_extract(&param)?;
+    Ok(())
+}
+
+struct Dataset {
+    file_code: &'static str,
+    zip_name: &'static str,
+    csv_name: &'static str,
+    ipv6: bool,
+}
+
+const DATASET_IPV4: Dataset = Dataset {
+    file_code: "DB1LITECSV",
+    zip_name: "IP2LOCATION-LITE-DB1.CSV.ZIP",
+    csv_name: "IP2LOCATION-LITE-DB1.CSV",
+    ipv6: false,
+};
+
+const DATASET_IPV6: Dataset = Dataset {
+    file_code: "DB1LITECSVIPV6",
+    zip_name: "IP2LOCATION-LITE-DB1.IPV6.CSV.ZIP",
+    csv_name: "IP2LOCATION-LITE-DB1.IPV6.CSV",
+    ipv6: true,
+};
+
+// 设置了 UNZIP_DIR 时先解压再读取，否则直接从 ZIP 中读取
+fn read_zip(zip_path: &Path, param: &Param, dataset: &Dataset) -> error::Result<Vec<extract::IpLocation>> {
+    match &param.unzip_dir {
+        Some(unzip_dir) => {
+            let csv_path = file_tool::unzip(zip_path, Path::new(unzip_dir), dataset.csv_name)?;
+            println!("unzip file successed! path:{}", csv_path.display());
+            file_tool::read_csv(&csv_path, param.strict_csv)
+        },
+        None => file_tool::read_csv_zip(zip_path, dataset.csv_name, param.strict_csv),
     }
-    let download_dir = format!("{}/IP2LOCATION-LITE-DB1.CSV.ZIP", param.download_dir);
-    let download_dir = Path::new(&download_dir);
-    file_tool::download_file(&url, &download_dir).unwrap_or_else(|e| {
-        panic!("download file error {}", e)
-    });
-    println!("download file successed! path:{}", download_dir.display());
+}
 
-    let unzip_dir = Path::new(&param.unzip_dir);
-    file_tool::unzip(&download_dir, unzip_dir).unwrap_or_else(|e| {
-        panic!("unzip file error {}", e)
-    });
-    println!("unzip file successed! path:{}", unzip_dir.display());
+struct Outputs {
+    // 每个 profile 选中的地址段
+    ranges: Vec<Vec<(u128, u128)>>,
+    // 每个 profile 的输出文件名与内容
+    files: Vec<(String, String)>,
+    // 每条覆盖对每个 profile 的影响
+    effects: Vec<(String, overrides::Effect)>,
+}
 
-    let list: Vec<extract::IpLocation> = file_tool::read_csv::<extract::IpLocation>(&unzip_dir.join(Path::new("IP2LOCATION-LITE-DB1.CSV"))).unwrap_or_else(|e| {
-        panic!("read csv file error {}", e)
-    });
+fn build_outputs(list: &[extract::IpLocation], param: &Param, ipv6: bool) -> error::Result<Outputs> {
+    let overrides = overrides::read_files(&param.override_files)?;
+    let domains = domain::read_files(&param.domain_files)?;
+    let mut selected_ranges = vec![];
+    let mut files = vec![];
+    let mut effects = vec![];
+    for profile in &param.profiles {
+        let include = match profile.mode {
+            profile::Mode::Proxy => overrides::Action::Proxy,
+            profile::Mode::Direct => overrides::Action::Direct,
+        };
+        let selected = if profile.overrides { overrides.as_slice() } else { &[] };
+        let (ranges, list) = overrides::apply(&profile.ranges(list, ipv6)?, selected, include, ipv6);
+        let own_domains = match &profile.domains {
+            Some(files) => Some(domain::read_files(files)?),
+            None => None,
+        };
+        let options = extract::RuleOptions {
+            domains: own_domains.as_deref().unwrap_or(&domains),
+            no_resolve: profile.no_resolve.unwrap_or(param.no_resolve),
+            ipv6_style: profile.ipv6_style.unwrap_or(param.ipv6_style),
+        };
+        files.push((profile.output.clone(), profile.render(&ranges, ipv6, &options)?));
+        effects.extend(list.into_iter().map(|x| (profile.name.clone(), x)));
+        selected_ranges.push(ranges);
+    }
+    Ok(Outputs { ranges: selected_ranges, files, effects })
+}
+
+// 影响输出的全部输入：下载文件的 ETag/Last-Modified、profile、参数，以及覆盖文件和域名规则文件的内容
+// 文件每次运行重新读取，daemon 模式下修改后下一次运行即重新生成，读取失败时记录错误，下一次读取成功时也会重新生成
+fn fingerprint(param: &Param, validators: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{}\n{}\n", env!("CARGO_PKG_VERSION"), validators));
+    hasher.update(format!("{:?}\n{:?}\n{:?}\n{:?}\n", param.input, param.ipv6, param.strict_csv, param.exclude_reserved));
+    hasher.update(format!("{:?}\n{:?}\n{:?}\n", param.guardrails, param.no_resolve, param.ipv6_style));
+    hasher.update(format!("{:?}\n{:?}\n{:?}\n", param.override_files, param.domain_files, param.profiles));
+    let files = param.override_files.iter().map(|(_, x)| x)
+        .chain(&param.domain_files)
+        .chain(param.profiles.iter().flat_map(|x| x.domains.iter().flatten()));
+    for path in files {
+        match fs::read(path) {
+            Ok(content) => hasher.update(Sha256::digest(content)),
+            Err(e) => hasher.update(format!("{}: {}", path.display(), e)),
+        }
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+// 下载 -> 解压 -> 提取 -> 格式化 -> 写入，全部生成成功后才写文件，失败时保留上一次的输出
+// 返回每个输出文件的变化情况
+fn run_extract(param: &Param) -> error::Result<Vec<String>> {
+    let dataset = if param.ipv6 { &DATASET_IPV6 } else { &DATASET_IPV4 };
+    let output_dir = Path::new(&param.output_dir);
+    let download_dir = format!("{}/{}", param.download_dir, dataset.zip_name);
+    let download_dir = Path::new(&download_dir);
+    let list = match &param.input {
+        // 直接使用已经下载好的 ZIP 或 CSV，不访问网络
+        Some(input) if input.to_lowercase().ends_with(".zip") => read_zip(Path::new(input), param, dataset)?,
+        Some(input) => file_tool::read_csv_input(input, dataset.csv_name, param.strict_csv)?,
+        None => {
+            if param.offline {
+                println!("offline mode, use downloaded file! path:{}", download_dir.display());
+            } else {
+                let url = match (&param.download_url, &param.ip2location_token) {
+                    (Some(url), _) => url.to_string(),
+                    (None, None) => {
+                        println!("Missing ENV parameter 'IP2LOCATION_TOKEN', Use default download url");
+                        format!("https://download.ip2location.com/lite/{}", dataset.zip_name)
+                    },
+                    (None, Some(token)) => format!("https://www.ip2location.com/download/?token={}&file={}", token, dataset.file_code),
+                };
+                let status = file_tool::download_file(&url, download_dir, &param.download_options)?;
+                let outputs_exist = param.profiles.iter().all(|x| output_dir.join(&x.output).exists());
+                // 配置或覆盖文件修改后即使数据库没有更新也要重新生成
+                let unchanged = guard::read_fingerprint(output_dir) == Some(fingerprint(param, &file_tool::download_validators(download_dir)));
+                if status == file_tool::DownloadStatus::NotModified && outputs_exist && unchanged && !param.force {
+                    println!("file not modified, skip! path:{}", download_dir.display());
+                    return Ok(vec!["database not modified".to_string()]);
+                }
+                println!("download file successed! path:{}", download_dir.display());
+            }
+            read_zip(download_dir, param, dataset)?
+        },
+    };
     println!("read csv file successed!");
-    
-    let str_list = extract::collect(&list, "CN").unwrap_or_else(|e| {
-        panic!("collect ip error {}", e)
-    });
+
+    let Outputs { ranges, files: contents, effects } = build_outputs(&list, param, dataset.ipv6)?;
     println!("collect ip successed!");
-    
-    let format_list = extract::format_proxifier(&str_list);
+    for (name, effect) in &effects {
+        println!("override {}: {}", name, effect);
+    }
     println!("format ip successed!");
+    // 每个 profile 分别检查，与该 profile 上一次发布时的统计比较
+    let previous = guard::read_state(output_dir);
+    let mut failures = vec![];
+    let mut states = vec![];
+    for (profile, ranges) in param.profiles.iter().zip(&ranges) {
+        let stats = guard::RunStats::new(list.len(), ranges, dataset.ipv6);
+        let last = previous.iter().find(|(name, _)| *name == profile.name).map(|(_, x)| x);
+        let guardrails = param.guardrails.with(&profile.guardrails);
+        failures.extend(guard::failures(&guardrails, &stats, last, ranges).into_iter().map(|x| format!("{}: {}", profile.name, x)));
+        states.push((profile.name.clone(), stats));
+    }
+    guard::report(&failures)?;
+    for (name, stats) in &states {
+        println!("check ranges successed! profile:{} rows:{} ranges:{} addresses:{}", name, stats.rows, stats.ranges, stats.addresses);
+    }
 
-    let output_dir = format!("{}/proxifier.txt", param.output_dir);
-    let output_dir = Path::new(&output_dir);
-    file_tool::write_file(output_dir, format_list).unwrap_or_else(|e| {
-        panic!("write file error {}", e)
-    });
-    println!("write file successed! path:{}", output_dir.display());
-
-    let format_list2 = extract::format_clash(&str_list);
-    println!("format clash successed!");
-
-    let output_dir = format!("{}/clash.txt", param.output_dir);
-    let output_dir = Path::new(&output_dir);
-    file_tool::write_file(output_dir, format_list2).unwrap_or_else(|e| {
-        panic!("write file error {}", e)
-    });
+    let files = contents.iter().map(|(name, content)| (output_dir.join(name), content.to_string())).collect::<Vec<_>>();
+    let changes = files.iter().map(|(path, content)| file_tool::line_changes(path, content)).collect::<Vec<_>>();
+    // 与上一次的输出比较第一个 profile 的地址段变化，附在运行日志中
+    // 上一次的输出可能是手工修改过或旧版本生成的，无法解析时只是不比较
+    let range_changes = match files.first().map(|(path, content)| (path, fs::read_to_string(path), content)) {
+        Some((path, Ok(old), new)) => match diff::output_ranges(&old, "*") {
+            Ok(old) => {
+                let new = diff::output_ranges(new, "*")?;
+                Some(diff::diff(&old, &new).summary_line())
+            },
+            Err(e) => {
+                eprintln!("skip range changes, read previous output error {}, {}", path.display(), e);
+                None
+            },
+        },
+        _ => None,
+    };
+    if changes.iter().any(|x| x != "unchanged") {
+        // 统计一起保存，回滚后下一次运行与回滚到的版本比较
+        let names = contents.iter().map(|(name, _)| name.as_str()).chain([guard::STATE_NAME]).collect::<Vec<_>>();
+        if let Some(generation) = file_tool::backup_generation(output_dir, &names, param.keep_generations)? {
+            println!("backup outputs successed! path:{}", generation.display());
+        }
+    }
+    file_tool::write_files(&files)?;
+    guard::write_state(output_dir, &states)?;
+    guard::write_fingerprint(output_dir, &fingerprint(param, &file_tool::download_validators(download_dir)))?;
     println!("write file successed! path:{}", output_dir.display());
+    let mut summary = contents.iter().zip(changes).map(|((name, _), x)| format!("{}: {}", name, x)).collect::<Vec<_>>();
+    summary.extend(range_changes);
+    if !effects.is_empty() {
+        // 同一条覆盖用于多个 profile 时只计一次，任一 profile 的结果改变即算改变
+        let mut items = HashMap::new();
+        for (_, effect) in &effects {
+            *items.entry(effect.item.source.as_str()).or_insert(false) |= effect.changed.is_some_and(|x| x > 0);
+        }
+        let changed = items.values().filter(|x| **x).count();
+        summary.push(format!("overrides: {} of {} changed the result", changed, items.len()));
+    }
+    Ok(summary)
+}
 
+fn main_rollback(param: &Param) -> error::Result<()> {
+    let generation = file_tool::rollback_generation(Path::new(&param.output_dir))?;
+    println!("rollback successed! restore outputs from {}", generation.display());
     Ok(())
 }
 
+fn main_daemon(args: &Args, param: &Param) -> error::Result<()> {
+    let schedule = args.value("schedule")?.unwrap_or("1d".to_string());
+    let schedule = schedule::Schedule::parse(&schedule).map_err(|e| Error::Config(format!("invalid schedule {}: {}", schedule, e)))?;
+    loop {
+        let begin = Instant::now();
+        match run_extract(param) {
+            Ok(summary) => println!("run successed in {:.1}s, {}", begin.elapsed().as_secs_f64(), summary.join(", ")),
+            Err(e) => eprintln!("run failed in {:.1}s with exit code {}, keep last outputs: {}", begin.elapsed().as_secs_f64(), e.exit_code(), e),
+        }
+        let (next, delay) = schedule.next();
+        println!("next run at {}", next.format("%Y-%m-%d %H:%M:%S %:z"));
+        thread::sleep(delay);
+    }
+}
 
-#[allow(dead_code)]
-fn main_ipv6() -> Result<(), Box<dyn std::error::Error>> {
-    let param = param_analysis();
+// 按文件名选择数据集，ZIP 中读取对应的 CSV
+fn read_database(input: &str, param: &Param) -> error::Result<Vec<extract::IpLocation>> {
+    let dataset = if input.to_uppercase().contains("IPV6") { &DATASET_IPV6 } else { &DATASET_IPV4 };
+    file_tool::read_csv_input(input, dataset.csv_name, param.strict_csv)
+}
 
-    let url;
-    if param.ip2location_token == None {
-       url = "https://download.ip2location.com/lite/IP2LOCATION-LITE-DB1.IPV6.CSV.ZIP".to_string();
-    } else {
-        url = format!("https://www.ip2location.com/download/?token={}&file=DB1LITECSVIPV6", param.ip2location_token.unwrap());
+// --csv 指定的数据库，否则使用 UNZIP_DIR 下的 CSV 或 DOWNLOAD_DIR 下的 ZIP
+fn default_database(args: &Args, param: &Param) -> error::Result<String> {
+    if let Some(x) = args.value("csv")? {
+        return Ok(x);
     }
-    let download_dir = format!("{}/IP2LOCATION-LITE-DB1.IPV6.CSV.ZIP", param.download_dir);
-    let download_dir = Path::new(&download_dir);
-    file_tool::download_file(&url, &download_dir).unwrap_or_else(|e| {
-        panic!("download file error {}", e)
-    });
-    println!("download file successed! path:{}", download_dir.display());
+    // 优先使用 IPv6 版本的数据库，它同时包含 IPv4
+    let datasets = [&DATASET_IPV6, &DATASET_IPV4];
+    let unzipped = param.unzip_dir.iter()
+        .flat_map(|dir| datasets.map(|x| Path::new(dir).join(x.csv_name)))
+        .find(|x| x.exists());
+    let zipped = datasets.map(|x| Path::new(&param.download_dir).join(x.zip_name)).into_iter().find(|x| x.exists());
+    let path = unzipped.or(zipped)
+        .ok_or_else(|| Error::Config(format!("no database found in {}, run extract first or use --csv", param.download_dir)))?;
+    Ok(path.display().to_string())
+}
 
-    let unzip_dir = Path::new(&param.unzip_dir);
-    file_tool::unzip(&download_dir, unzip_dir).unwrap_or_else(|e| {
-        panic!("unzip file error {}", e)
-    });
-    println!("unzip file successed! path:{}", unzip_dir.display());
+fn load_index(args: &Args, param: &Param) -> error::Result<lookup::IpIndex> {
+    let input = default_database(args, param)?;
+    let index = lookup::IpIndex::new(read_database(&input, param)?)?;
+    eprintln!("load {} ranges from {}", index.len(), input);
+    Ok(index)
+}
 
-    let list: Vec<extract::IpLocation> = file_tool::read_csv::<extract::IpLocation>(&unzip_dir.join(Path::new("IP2LOCATION-LITE-DB1.IPV6.CSV"))).unwrap_or_else(|e| {
-        panic!("read csv file error {}", e)
-    });
-    println!("read csv file successed!");
-    
-    let str_list = extract::collect_ipv6(&list, "CN").unwrap_or_else(|e| {
-        panic!("collect ip error {}", e)
-    });
-    println!("collect ip successed!");
-    
-    let format_list = extract::format_proxifier(&str_list);
-    println!("format ip successed!");
+// 生成的输出文件（.txt）只区分在不在规则中，CSV、ZIP 按国家比较
+fn read_diff_ranges(input: &str, param: &Param) -> error::Result<Vec<(u128, u128, String)>> {
+    if input.to_lowercase().ends_with(".txt") {
+        let content = fs::read_to_string(input).map_err(|e| Error::Parse(format!("read file error {}, {}", input, e)))?;
+        return diff::output_ranges(&content, "*");
+    }
+    diff::country_ranges(&read_database(input, param)?)
+}
 
-    let output_dir = format!("{}/proxifier.txt", param.output_dir);
-    let output_dir = Path::new(&output_dir);
-    file_tool::write_file(output_dir, format_list).unwrap_or_else(|e| {
-        panic!("write file error {}", e)
-    });
-    println!("write file successed! path:{}", output_dir.display());
+fn main_diff(args: &Args, param: &Param) -> error::Result<()> {
+    let (old, new) = match &args.positional[..] {
+        [old, new] => (old, new),
+        _ => return Err(Error::Config("usage: ip-extract diff <old> <new> [--format text|json] [--limit 20]".to_string())),
+    };
+    let diff = diff::diff(&read_diff_ranges(old, param)?, &read_diff_ranges(new, param)?);
+    match args.value("format")?.as_deref() {
+        None | Some("text") => {
+            let limit = param::parse_number(args, "limit")?.unwrap_or(20);
+            print!("{}", diff.format_text(limit));
+        },
+        Some("json") => println!("{}", diff.to_json()),
+        Some(x) => return Err(Error::Config(format!("unknown format: {}", x))),
+    }
+    Ok(())
+}
+
+fn main_stats(args: &Args, param: &Param) -> error::Result<()> {
+    let input = match args.positional.first() {
+        Some(x) => x.to_string(),
+        None => default_database(args, param)?,
+    };
+    let list = read_database(&input, param)?;
+    let ipv6 = list.iter().any(|x| x.ip_end.parse::<u128>().map(|x| x > u32::MAX as u128).unwrap_or(false));
+    let Outputs { ranges, files: contents, .. } = build_outputs(&list, param, ipv6)?;
+    let outputs = contents.iter().map(|(name, content)| (name.as_str(), content.as_str())).collect::<Vec<_>>();
+    // 统计第一个 profile 的结果
+    let ranges = ranges.into_iter().next().unwrap_or_default();
+    let stats = stats::Stats::new(&list, &ranges, &param.exclude_reserved, ipv6, &outputs)?;
+    match args.value("format")?.as_deref() {
+        None | Some("table") => print!("{}", stats.format_table()),
+        Some("csv") => print!("{}", stats.format_csv()),
+        Some("json") => println!("{}", stats.to_json()),
+        Some(x) => return Err(Error::Config(format!("unknown format: {}", x))),
+    }
+    Ok(())
+}
 
-    let format_list2 = extract::format_clash_ipv6(&str_list);
-    println!("format clash successed!");
+// 把 IPv4 地址空间画成希尔伯特曲线的 PNG 图片，默认写到输出目录下的 map.png
+fn main_map(args: &Args, param: &Param) -> error::Result<()> {
+    let input = match args.positional.first() {
+        Some(x) => x.to_string(),
+        None => default_database(args, param)?,
+    };
+    let list = read_database(&input, param)?;
+    let ipv6 = list.iter().any(|x| x.ip_end.parse::<u128>().map(|x| x > u32::MAX as u128).unwrap_or(false));
+    // 画第一个 profile 的结果
+    let ranges = build_outputs(&list, param, ipv6)?.ranges.into_iter().next().unwrap_or_default();
+    let mut options = map::MapOptions::default();
+    if let Some(x) = param::parse_number(args, "map-size")? {
+        options.size = x;
+    }
+    if let Some(x) = args.value("map-mode")? {
+        options.mode = x.parse()?;
+    }
+    options.labels = args.flag("map-labels")?;
+    let direct = param.profiles.first().is_some_and(|x| x.mode == profile::Mode::Direct);
+    let bytes = map::render(&list, &ranges, direct, &param.exclude_reserved, ipv6, &options)?;
+    let path = args.value("map-output")?.unwrap_or(format!("{}/map.png", param.output_dir));
+    file_tool::write_files(&[(Path::new(&path).to_path_buf(), bytes)])?;
+    println!("write map {}", path);
+    Ok(())
+}
 
-    let output_dir = format!("{}/clash.txt", param.output_dir);
-    let output_dir = Path::new(&output_dir);
-    file_tool::write_file(output_dir, format_list2).unwrap_or_else(|e| {
-        panic!("write file error {}", e)
-    });
-    println!("write file successed! path:{}", output_dir.display());
+fn main_lookup(args: &Args, param: &Param) -> error::Result<()> {
+    let index = load_index(args, param)?;
 
+    let print = |line: &str| {
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+        match line.parse::<IpAddr>() {
+            Err(_) => println!("{}\tinvalid ip", line),
+            Ok(ip) => match index.lookup(ip) {
+                None => println!("{}\t-\t-", line),
+                Some(x) => println!("{}\t{}\t{}", line, x.country_code, x.country_name),
+            },
+        }
+    };
+    if args.positional.is_empty() {
+        for line in io::stdin().lock().lines() {
+            print(&line.map_err(|e| Error::Parse(format!("read stdin error {}", e)))?);
+        }
+    } else {
+        args.positional.iter().for_each(|x| print(x));
+    }
     Ok(())
 }
 
-struct Param {
-    ip2location_token: Option<String>,
-    download_dir: String,
-    unzip_dir: String,
-    output_dir: String,
+// 每个地址输出使用的写法和标准写法，--to 指定写法时只输出这一种，否则逐行列出所有写法
+fn main_convert(args: &Args) -> error::Result<()> {
+    let to = args.value("to")?;
+    // 无法解析的输入写到 stderr，全部处理完后以 Parse 错误退出
+    let mut invalid = 0;
+    let mut print = |line: &str| {
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+        let conversion = match ip_tool::convert(line) {
+            Err(e) => {
+                invalid += 1;
+                return eprintln!("{}\tinvalid ip, {}", line, e);
+            },
+            Ok(x) => x,
+        };
+        match &to {
+            None => {
+                println!("{}\t{}\t{}", line, conversion.notation, conversion.address);
+                conversion.notations.iter().for_each(|(name, text)| println!("\t{}\t{}", name, text));
+            },
+            Some(to) => match conversion.notations.iter().find(|x| &x.0 == to) {
+                None => println!("{}\t{}\tnotation {} is not applicable", line, conversion.notation, to),
+                Some((_, text)) => println!("{}\t{}\t{}", line, conversion.notation, text),
+            },
+        }
+    };
+    if let Some(to) = &to {
+        let names = ip_tool::convert("0.0.0.0").into_iter().chain(ip_tool::convert("::")).flat_map(|x| x.notations).map(|x| x.0).collect::<Vec<_>>();
+        if !names.contains(to) {
+            return Err(Error::Config(format!("invalid parameter 'TO': {}, expected one of {}", to, names.join(", "))));
+        }
+    }
+    if args.positional.is_empty() {
+        for line in io::stdin().lock().lines() {
+            print(&line.map_err(|e| Error::Parse(format!("read stdin error {}", e)))?);
+        }
+    } else {
+        args.positional.iter().for_each(|x| print(x));
+    }
+    if invalid > 0 {
+        return Err(Error::Parse(format!("{} invalid ip", invalid)));
+    }
+    Ok(())
 }
 
-fn param_analysis() -> Param {
-    let ip2location_token = match env::var_os("IP2LOCATION_TOKEN") {
-        None => {
-            println!("Missing ENV parameter 'IP2LOCATION_TOKEN', Use default download url");
+fn main_serve(args: &Args, param: &Param) -> error::Result<()> {
+    let listen = args.value("listen")?.unwrap_or("0.0.0.0:8080".to_string());
+    let addr = listen.parse().map_err(|e| Error::Config(format!("invalid listen address {}: {}", listen, e)))?;
+    let index = match load_index(args, param) {
+        Ok(x) => Some(x),
+        Err(e) => {
+            eprintln!("load lookup index error {}, lookup api is disabled", e);
             None
         },
-        Some(x) => Some(x.to_str().unwrap().to_string()),
-    };
-    let download_dir = match env::var_os("DOWNLOAD_DIR") {
-        None => "/data/ip-extract".to_string(),
-        Some(x) => x.to_str().unwrap().to_string(),
     };
-    let unzip_dir = match env::var_os("UNZIP_DIR") {
-        None => "/data/ip-extract".to_string(),
-        Some(x) => x.to_str().unwrap().to_string(),
+    let state = server::ServerState {
+        output_dir: Path::new(&param.output_dir).to_path_buf(),
+        files: param.profiles.iter().map(|x| x.output.clone()).collect(),
+        index,
     };
-    let output_dir = match env::var_os("OUTPUT_DIR"){
-        None => "/data/ip-extract".to_string(),
-        Some(x) => x.to_str().unwrap().to_string(),
-    };
-    Param {
-        ip2location_token,
-        download_dir,
-        unzip_dir,
-        output_dir,
+    server::serve(addr, state).map_err(|e| Error::Output(format!("serve error {}", e)))
+}
+
+#[cfg(test)]
+mod main_test {
+    use std::{fs::File, io::{Read, Write}, path::PathBuf};
+
+    use crate::*;
+
+    // 固定返回 ETag "v1" 的 ZIP，带 If-None-Match 时返回 304
+    fn mock_server(body: Vec<u8>) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = vec![];
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                }
+                if String::from_utf8_lossy(&request).to_lowercase().contains("if-none-match: \"v1\"") {
+                    let _ = stream.write_all(b"HTTP/1.1 304 Not Modified\r\nContent-Length: 0\r\n\r\n");
+                } else {
+                    let head = format!("HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: {}\r\n\r\n", body.len());
+                    let _ = stream.write_all(&[head.as_bytes(), &body].concat());
+                }
+            }
+        });
+        format!("http://{}/db.zip", addr)
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("ip-extract-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // 下载文件至少 4096 字节，不压缩的填充内容撑大 ZIP
+    fn database_zip(dir: &Path) -> Vec<u8> {
+        let path = dir.join("db.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        let stored = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        zip.start_file(DATASET_IPV4.csv_name, stored).unwrap();
+        zip.write_all(b"\"16777216\",\"16777471\",\"US\",\"United States of America\"\n\"16777472\",\"16778239\",\"CN\",\"China\"\n").unwrap();
+        zip.start_file("README", stored).unwrap();
+        zip.write_all(&[b'x'; 5000]).unwrap();
+        zip.finish().unwrap();
+        fs::read(path).unwrap()
+    }
+
+    fn param(dir: &Path, url: &str, extra: &[&str]) -> Param {
+        let mut list = vec![
+            "--download-url".to_string(), url.to_string(),
+            "--download-dir".to_string(), dir.join("download").display().to_string(),
+            "--output-dir".to_string(), dir.join("output").display().to_string(),
+        ];
+        list.extend(extra.iter().map(|x| x.to_string()));
+        param::param_analysis(&Args::parse(list)).unwrap()
+    }
+
+    #[test]
+    fn override_change_test() {
+        let dir = temp_dir("main-override");
+        let url = mock_server(database_zip(&dir));
+        let force_proxy = dir.join("force-proxy.txt");
+        fs::write(&force_proxy, "8.8.8.0/24\n").unwrap();
+        let param = param(&dir, &url, &["--force-proxy", &force_proxy.display().to_string()]);
+        let output = dir.join("output/clash.txt");
+
+        run_extract(&param).unwrap();
+        assert!(fs::read_to_string(&output).unwrap().contains("8.8.8.0/24"));
+        // 数据库没有更新，覆盖文件也没有修改时跳过
+        assert_eq!(vec!["database not modified".to_string()], run_extract(&param).unwrap());
+
+        fs::write(&force_proxy, "8.8.8.0/24\n9.9.9.0/24\n").unwrap();
+        assert_ne!(vec!["database not modified".to_string()], run_extract(&param).unwrap());
+        assert!(fs::read_to_string(&output).unwrap().contains("9.9.9.0/24"));
+        assert_eq!(vec!["database not modified".to_string()], run_extract(&param).unwrap());
+    }
+
+    #[test]
+    fn domain_and_profile_change_test() {
+        let dir = temp_dir("main-domain");
+        let url = mock_server(database_zip(&dir));
+        let domains = dir.join("domains.txt");
+        fs::write(&domains, "example.com\n").unwrap();
+        let config = dir.join("config.toml");
+        let profile = format!("[[profile]]\nname = \"clash\"\nexclude = [\"CN\"]\ndomains = [\"{}\"]\nformat = \"clash\"\noutput = \"clash.txt\"\n", domains.display());
+        fs::write(&config, &profile).unwrap();
+        let config_arg = config.display().to_string();
+        let param = param(&dir, &url, &["--config", &config_arg]);
+        let output = dir.join("output/clash.txt");
+
+        run_extract(&param).unwrap();
+        assert_eq!(vec!["database not modified".to_string()], run_extract(&param).unwrap());
+
+        // daemon 模式下每次运行重新读取域名规则文件
+        fs::write(&domains, "example.com\nexample.org\n").unwrap();
+        run_extract(&param).unwrap();
+        assert!(fs::read_to_string(&output).unwrap().contains("example.org"));
+        assert_eq!(vec!["database not modified".to_string()], run_extract(&param).unwrap());
+
+        fs::write(&config, format!("{}add = [\"8.8.8.0/24\"]\n", profile)).unwrap();
+        let param = self::param(&dir, &url, &["--config", &config_arg]);
+        run_extract(&param).unwrap();
+        assert!(fs::read_to_string(&output).unwrap().contains("8.8.8.0/24"));
     }
 }
@@ -0,0 +1,408 @@
+use std::{collections::HashMap, str::FromStr};
+
+use crate::{error::{self, Error}, extract::IpLocation, ip_tool};
+
+// IPv6 数据集中 ::ffff:0:0/96 内的部分对应 IPv4
+
+// 按策略着色：走代理、直连、特殊用途、未分配
+const PROXY: [u8; 3] = [230, 85, 13];
+const DIRECT: [u8; 3] = [49, 163, 84];
+const RESERVED: [u8; 3] = [110, 110, 110];
+const UNASSIGNED: [u8; 3] = [24, 24, 24];
+const TEXT: [u8; 3] = [255, 255, 255];
+const GRID: [u8; 3] = [160, 160, 160];
+const OTHER: [u8; 3] = [210, 210, 210];
+
+// 图例中最多列出的国家个数，其余的国家都画成 OTHER
+const LEGEND_COUNTRIES: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapMode {
+    // 按国家着色
+    Country,
+    // 按代理/直连的判定着色
+    Policy,
+}
+
+impl FromStr for MapMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> error::Result<MapMode> {
+        match s {
+            "country" => Ok(MapMode::Country),
+            "policy" => Ok(MapMode::Policy),
+            x => Err(Error::Config(format!("invalid parameter 'MAP_MODE': {}", x))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MapOptions {
+    // 地图的边长，2 的幂，4096 时每个像素对应一个 /24
+    pub size: u32,
+    pub mode: MapMode,
+    // 画出 /8 的网格和编号
+    pub labels: bool,
+}
+
+impl Default for MapOptions {
+    fn default() -> Self {
+        MapOptions {
+            size: 4096,
+            mode: MapMode::Policy,
+            labels: false,
+        }
+    }
+}
+
+// 5x7 点阵字体，每行低 5 位有效
+const FONT: [(char, [u8; 7]); 41] = [
+    (' ', [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000]),
+    ('0', [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110]),
+    ('1', [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110]),
+    ('2', [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111]),
+    ('3', [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110]),
+    ('4', [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010]),
+    ('5', [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110]),
+    ('6', [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110]),
+    ('7', [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000]),
+    ('8', [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110]),
+    ('9', [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100]),
+    ('A', [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001]),
+    ('B', [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110]),
+    ('C', [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110]),
+    ('D', [0b11100, 0b10010, 0b10001, 0b10001, 0b10001, 0b10010, 0b11100]),
+    ('E', [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111]),
+    ('F', [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000]),
+    ('G', [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111]),
+    ('H', [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001]),
+    ('I', [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110]),
+    ('J', [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100]),
+    ('K', [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001]),
+    ('L', [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111]),
+    ('M', [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001]),
+    ('N', [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001]),
+    ('O', [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110]),
+    ('P', [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000]),
+    ('Q', [0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101]),
+    ('R', [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001]),
+    ('S', [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110]),
+    ('T', [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100]),
+    ('U', [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110]),
+    ('V', [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100]),
+    ('W', [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010]),
+    ('X', [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001]),
+    ('Y', [0b10001, 0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100]),
+    ('Z', [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111]),
+    ('/', [0b00000, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b00000]),
+    ('.', [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b01100]),
+    ('-', [0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000]),
+    (':', [0b00000, 0b01100, 0b01100, 0b00000, 0b01100, 0b01100, 0b00000]),
+];
+
+// 希尔伯特曲线上的第 d 个点在边长为 n 的正方形中的坐标
+pub fn d2xy(n: u32, d: u64) -> (u32, u32) {
+    let (mut x, mut y) = (0_u32, 0_u32);
+    let mut t = d;
+    let mut s = 1_u32;
+    while s < n {
+        let rx = (1 & (t / 2)) as u32;
+        let ry = (1 & (t ^ rx as u64)) as u32;
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
+
+// 国家代码的颜色由哈希得到色相，同一个国家每次的颜色相同
+fn country_color(code: &str) -> [u8; 3] {
+    let hash = code.bytes().fold(0x811c9dc5_u32, |hash, x| (hash ^ x as u32).wrapping_mul(0x01000193));
+    let hue = (hash % 360) as f64 / 60.0;
+    let (saturation, value) = (0.65, 0.9);
+    let c = value * saturation;
+    let x = c * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = value - c;
+    [r, g, b].map(|x| ((x + m) * 255.0).round() as u8)
+}
+
+// 转换为 IPv4 的地址段，IPv6 数据集只取 ::ffff:0:0/96 内的部分
+fn ipv4_range(start: u128, end: u128, ipv6: bool) -> Option<(u32, u32)> {
+    let (start, end) = if ipv6 {
+        (start.max(ip_tool::MAPPED).checked_sub(ip_tool::MAPPED)?, end.checked_sub(ip_tool::MAPPED)?.min(u32::MAX as u128))
+    } else {
+        (start, end.min(u32::MAX as u128))
+    };
+    if start > end {
+        return None;
+    }
+    Some((start as u32, end as u32))
+}
+
+// 每个像素对应一个地址块，地址段覆盖一个块的一半以上时涂上颜色
+fn paint(blocks: &mut [u16], bits: u32, (start, end): (u32, u32), color: u16) {
+    let (start, end) = (start as u64, end as u64);
+    let block = 1_u64 << bits;
+    for index in (start >> bits)..=(end >> bits) {
+        let block_start = index << bits;
+        let overlap = end.min(block_start + block - 1) - start.max(block_start) + 1;
+        if overlap * 2 >= block {
+            blocks[index as usize] = color;
+        }
+    }
+}
+
+struct Canvas {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Canvas {
+        Canvas { width, height, data: vec![0; width as usize * height as usize * 3] }
+    }
+
+    fn set(&mut self, x: u32, y: u32, color: [u8; 3]) {
+        if x < self.width && y < self.height {
+            let index = (y as usize * self.width as usize + x as usize) * 3;
+            self.data[index..index + 3].copy_from_slice(&color);
+        }
+    }
+
+    fn fill(&mut self, x: u32, y: u32, width: u32, height: u32, color: [u8; 3]) {
+        for dy in 0..height {
+            for dx in 0..width {
+                self.set(x + dx, y + dy, color);
+            }
+        }
+    }
+
+    // 每个字符占 6x8 个点，scale 为每个点的像素个数，不支持的字符画成空格
+    fn text(&mut self, x: u32, y: u32, scale: u32, str: &str, color: [u8; 3]) {
+        for (i, c) in str.to_uppercase().chars().enumerate() {
+            let glyph = FONT.iter().find(|(x, _)| *x == c).map(|(_, x)| x).unwrap_or(&FONT[0].1);
+            let left = x + i as u32 * 6 * scale;
+            for (row, bits) in glyph.iter().enumerate() {
+                for column in 0..5 {
+                    if bits & (0b10000 >> column) != 0 {
+                        self.fill(left + column * scale, y + row as u32 * scale, scale, scale, color);
+                    }
+                }
+            }
+        }
+    }
+
+    // 带黑色描边的文字，画在彩色的地图上也能看清
+    fn label(&mut self, x: u32, y: u32, scale: u32, str: &str) {
+        for (dx, dy) in [(0, 1), (2, 1), (1, 0), (1, 2)] {
+            self.text(x + dx * scale / 2, y + dy * scale / 2, scale, str, [0, 0, 0]);
+        }
+        self.text(x + scale / 2, y + scale / 2, scale, str, TEXT);
+    }
+
+    fn encode(&self) -> error::Result<Vec<u8>> {
+        let output_error = |e: png::EncodingError| Error::Output(format!("encode png error {}", e));
+        let mut result = vec![];
+        let mut encoder = png::Encoder::new(&mut result, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(output_error)?;
+        writer.write_image_data(&self.data).map_err(output_error)?;
+        writer.finish().map_err(output_error)?;
+        Ok(result)
+    }
+}
+
+// 按 xkcd 的方式把 IPv4 地址空间画成希尔伯特曲线，右侧是图例
+// selected 为输出中的地址段，direct 为 true 时它们直连、其余走代理，reserved 为特殊用途地址，与 collect 使用相同的数据
+pub fn render(list: &[IpLocation], selected: &[(u128, u128)], direct: bool, reserved: &[ip_tool::SpecialPurpose], ipv6: bool, options: &MapOptions) -> error::Result<Vec<u8>> {
+    let size = options.size;
+    if !size.is_power_of_two() || !(256..=4096).contains(&size) {
+        return Err(Error::Config(format!("invalid parameter 'MAP_SIZE': {}, must be a power of two between 256 and 4096", size)));
+    }
+    // 每个像素对应 2^bits 个地址
+    let bits = 32 - 2 * size.trailing_zeros();
+    let mut palette = vec![UNASSIGNED];
+    let mut legend = vec![];
+    let mut blocks = vec![0_u16; size as usize * size as usize];
+    match options.mode {
+        MapMode::Policy => {
+            palette.extend([DIRECT, RESERVED, PROXY]);
+            // 先把有国家的地址涂成未选中时的策略，再涂选中的地址，特殊用途地址始终单独显示
+            let (other, chosen) = if direct { (3, 1) } else { (1, 3) };
+            for row in list.iter().filter(|x| x.country_code != "-") {
+                let (start, end) = row.range()?;
+                if let Some(range) = ipv4_range(start, end, ipv6) {
+                    paint(&mut blocks, bits, range, other);
+                }
+            }
+            for &(start, end) in selected {
+                if let Some(range) = ipv4_range(start, end, ipv6) {
+                    paint(&mut blocks, bits, range, chosen);
+                }
+            }
+            for range in ip_tool::special_purpose_ranges_ipv4(reserved) {
+                paint(&mut blocks, bits, range, 2);
+            }
+            legend.extend([("PROXY".to_string(), PROXY), ("DIRECT".to_string(), DIRECT), ("RESERVED".to_string(), RESERVED), ("UNASSIGNED".to_string(), UNASSIGNED)]);
+        },
+        MapMode::Country => {
+            let mut colors: HashMap<&str, u16> = HashMap::new();
+            let mut addresses: HashMap<&str, u64> = HashMap::new();
+            for row in list.iter().filter(|x| x.country_code != "-") {
+                let (start, end) = row.range()?;
+                let Some(range) = ipv4_range(start, end, ipv6) else { continue };
+                let color = *colors.entry(&row.country_code).or_insert_with(|| {
+                    palette.push(country_color(&row.country_code));
+                    palette.len() as u16 - 1
+                });
+                *addresses.entry(&row.country_code).or_default() += (range.1 - range.0) as u64 + 1;
+                paint(&mut blocks, bits, range, color);
+            }
+            let mut top = addresses.into_iter().collect::<Vec<_>>();
+            top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+            // 每个国家有自己的调色板位置，改掉颜色即可
+            for (code, _) in top.iter().skip(LEGEND_COUNTRIES) {
+                palette[colors[code] as usize] = OTHER;
+            }
+            let other = top.len() > LEGEND_COUNTRIES;
+            legend.extend(top.into_iter().take(LEGEND_COUNTRIES).map(|(code, _)| (code.to_string(), palette[colors[code] as usize])));
+            if other {
+                legend.push(("OTHER".to_string(), OTHER));
+            }
+            legend.push(("UNASSIGNED".to_string(), UNASSIGNED));
+        },
+    }
+
+    // 文字大小随地图缩放，4096 时每个点 4 个像素
+    let scale = (size / 1024).max(1);
+    let margin = 4 * scale;
+    let legend_width = 2 * margin + (7 + 3 + 10 * 6) * scale;
+    let legend_height = 2 * margin + legend.len() as u32 * 10 * scale;
+    let mut canvas = Canvas::new(size + legend_width, size.max(legend_height));
+    for (index, &color) in blocks.iter().enumerate() {
+        let (x, y) = d2xy(size, index as u64);
+        canvas.set(x, y, palette[color as usize]);
+    }
+    if options.labels {
+        let side = size / 16;
+        for prefix in 0..256_u64 {
+            let (x, y) = d2xy(size, (prefix << 24) >> bits);
+            let (x, y) = (x / side * side, y / side * side);
+            for i in 0..side {
+                canvas.set(x + i, y, GRID);
+                canvas.set(x, y + i, GRID);
+            }
+            canvas.label(x + scale, y + scale, scale, &prefix.to_string());
+        }
+    }
+    for (i, (name, color)) in legend.iter().enumerate() {
+        let (x, y) = (size + margin, margin + i as u32 * 10 * scale);
+        canvas.fill(x, y, 7 * scale, 7 * scale, *color);
+        canvas.text(x + 10 * scale, y, scale, name, TEXT);
+    }
+    canvas.encode()
+}
+
+#[cfg(test)]
+mod map_test {
+    use crate::{extract::row, ip_tool::SpecialPurpose, map::*};
+
+    fn decode(bytes: &[u8]) -> (png::OutputInfo, Vec<u8>) {
+        let mut reader = png::Decoder::new(bytes).read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        (info, data)
+    }
+
+    fn pixel(info: &png::OutputInfo, data: &[u8], (x, y): (u32, u32)) -> [u8; 3] {
+        let index = (y * info.width + x) as usize * 3;
+        [data[index], data[index + 1], data[index + 2]]
+    }
+
+    #[test]
+    fn d2xy_test() {
+        let n = 16;
+        let mut seen = std::collections::HashSet::new();
+        let mut last = d2xy(n, 0);
+        assert_eq!((0, 0), last);
+        seen.insert(last);
+        for d in 1..(n * n) as u64 {
+            let point = d2xy(n, d);
+            // 相邻的点在曲线上也相邻
+            assert_eq!(1, point.0.abs_diff(last.0) + point.1.abs_diff(last.1), "{}", d);
+            assert!(seen.insert(point));
+            last = point;
+        }
+        // 连续的 4^k 个点组成对齐的正方形
+        for block in 0..16_u64 {
+            let points = (block * 16..(block + 1) * 16).map(|d| d2xy(n, d)).collect::<Vec<_>>();
+            let (x, y) = (points[0].0 / 4, points[0].1 / 4);
+            assert!(points.iter().all(|p| p.0 / 4 == x && p.1 / 4 == y));
+        }
+    }
+
+    #[test]
+    fn render_policy_test() {
+        // 1.0.0.0/8 为 CN，2.0.0.0/8 为 US 并在输出中
+        let list = [row(16777216, 33554431, "CN"), row(33554432, 50331647, "US")];
+        let options = MapOptions { size: 256, mode: MapMode::Policy, labels: true };
+        let bytes = render(&list, &[(33554432, 50331647)], false, &[SpecialPurpose::Private], false, &options).unwrap();
+        let (info, data) = decode(&bytes);
+        assert_eq!(256, info.height);
+        assert!(info.width > 256);
+        // 256 时每个像素对应一个 /16
+        let at = |a: u64, b: u64| pixel(&info, &data, d2xy(256, a << 8 | b));
+        assert_eq!(DIRECT, at(1, 100));
+        assert_eq!(PROXY, at(2, 100));
+        assert_eq!(RESERVED, at(10, 100));
+        assert_eq!(UNASSIGNED, at(3, 100));
+
+        // 直连模式下选中的是 CN
+        let bytes = render(&list, &[(16777216, 33554431)], true, &[SpecialPurpose::Private], false, &options).unwrap();
+        let (info, data) = decode(&bytes);
+        let at = |a: u64| pixel(&info, &data, d2xy(256, a << 8 | 100));
+        assert_eq!((DIRECT, PROXY, RESERVED), (at(1), at(2), at(10)));
+    }
+
+    #[test]
+    fn render_country_test() {
+        // IPv6 数据集中只画 ::ffff:0:0/96 内的部分
+        let list = [row(0, 0xffff_00ff_ffff, "-"), row(0xffff_0100_0000, 0xffff_01ff_ffff, "JP"), row(0xffff_0200_0000, u128::MAX, "DE")];
+        let options = MapOptions { size: 256, mode: MapMode::Country, labels: false };
+        let bytes = render(&list, &[], false, &[], true, &options).unwrap();
+        let (info, data) = decode(&bytes);
+        let at = |a: u64| pixel(&info, &data, d2xy(256, a << 8));
+        assert_eq!(UNASSIGNED, at(0));
+        assert_eq!(country_color("JP"), at(1));
+        assert_eq!(country_color("DE"), at(255));
+        assert!(render(&list, &[], false, &[], true, &MapOptions { size: 1000, ..options }).is_err());
+        assert!("nothing".parse::<MapMode>().is_err());
+
+        // 21 个国家时地址最少的一个不在图例中，画成 OTHER，与图例一致
+        let codes = (0..=LEGEND_COUNTRIES as u128).map(|x| format!("C{:02}", x)).collect::<Vec<_>>();
+        let list = codes.iter().enumerate().map(|(i, code)| row((i as u128 + 1) << 24, ((i as u128 + 2) << 24) - 1, code)).collect::<Vec<_>>();
+        let (info, data) = decode(&render(&list, &[], false, &[], false, &options).unwrap());
+        let at = |a: u64| pixel(&info, &data, d2xy(256, a << 8));
+        assert_eq!(country_color("C00"), at(1));
+        assert_eq!(country_color("C19"), at(20));
+        assert_eq!(OTHER, at(21));
+    }
+}
@@ -0,0 +1,173 @@
+use std::{fmt, fs, net::IpAddr, path::{Path, PathBuf}};
+
+use crate::{error::{self, Error}, extract, ip_tool};
+
+// 覆盖数据库结果的动作：强制走代理或强制直连
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Proxy,
+    Direct,
+}
+
+impl Action {
+    fn name(&self) -> &'static str {
+        match self {
+            Action::Proxy => "force-proxy",
+            Action::Direct => "force-direct",
+        }
+    }
+}
+
+// 覆盖文件中的一行
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Override {
+    pub action: Action,
+    // 文件名和行号
+    pub source: String,
+    pub text: String,
+    pub start: IpAddr,
+    pub end: IpAddr,
+}
+
+// 一行可以是 CIDR、a-b 形式的范围或单个地址，# 之后为注释
+fn parse_line(line: &str) -> Option<(IpAddr, IpAddr)> {
+    if let Some((ip, prefix)) = line.split_once('/') {
+        let ip: IpAddr = ip.trim().parse().ok()?;
+        let cidr = format!("{}/{}", ip, prefix.trim());
+        return match ip {
+            IpAddr::V4(_) => ip_tool::cidr_to_range_ipv4(&cidr).map(|(start, end)| (IpAddr::from(start.to_be_bytes()), IpAddr::from(end.to_be_bytes()))),
+            IpAddr::V6(_) => ip_tool::cidr_to_range_ipv6(&cidr).map(|(start, end)| (IpAddr::from(start.to_be_bytes()), IpAddr::from(end.to_be_bytes()))),
+        };
+    }
+    let (start, end) = match line.split_once('-') {
+        Some((start, end)) => (start.trim().parse().ok()?, end.trim().parse().ok()?),
+        None => {
+            let ip: IpAddr = line.parse().ok()?;
+            (ip, ip)
+        },
+    };
+    match (start, end) {
+        (IpAddr::V4(x), IpAddr::V4(y)) if x <= y => Some((start, end)),
+        (IpAddr::V6(x), IpAddr::V6(y)) if x <= y => Some((start, end)),
+        _ => None,
+    }
+}
+
+pub fn parse(content: &str, action: Action, name: &str) -> error::Result<Vec<Override>> {
+    let mut result = vec![];
+    for (i, line) in content.lines().enumerate() {
+        let text = line.split('#').next().unwrap_or_default().trim();
+        if text.is_empty() {
+            continue;
+        }
+        let source = format!("{}:{}", name, i + 1);
+        let (start, end) = parse_line(text).ok_or_else(|| Error::Parse(format!("invalid override at {}: {}", source, text)))?;
+        result.push(Override { action, source, text: text.to_string(), start, end });
+    }
+    Ok(result)
+}
+
+// 每次运行重新读取，内容记在跳过生成用的指纹中，daemon 模式下修改覆盖文件后下一次运行即生效，不需要重启
+pub fn read_files(files: &[(Action, PathBuf)]) -> error::Result<Vec<Override>> {
+    let mut result = vec![];
+    for (action, path) in files {
+        let content = fs::read_to_string(path).map_err(|e| Error::Config(format!("read override file error {}, {}", path.display(), e)))?;
+        result.extend(parse(&content, *action, &file_name(path))?);
+    }
+    Ok(result)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_else(|| path.display().to_string())
+}
+
+// 一条覆盖对结果的影响，changed 为并入（include）或去掉的地址个数，None 表示数据集中无法表示
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Effect {
+    pub item: Override,
+    pub include: bool,
+    pub changed: Option<u128>,
+}
+
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} ({}): ", self.item.action.name(), self.item.text, self.item.source)?;
+        match self.changed {
+            None => write!(f, "not applicable to an ipv4 dataset"),
+            Some(0) => write!(f, "no change"),
+            Some(x) => write!(f, "{} {} addresses", if self.include { "added" } else { "removed" }, x),
+        }
+    }
+}
+
+// include 动作的覆盖并入结果，另一种从结果中去掉，都优先于数据库
+// 先并入再去掉，两种都写了的地址按去掉处理
+pub fn apply(ranges: &[(u128, u128)], overrides: &[Override], include: Action, ipv6: bool) -> (Vec<(u128, u128)>, Vec<Effect>) {
+    let mut ranges = ranges.to_vec();
+    let mut effects = vec![];
+    let ordered = overrides.iter().filter(|x| x.action == include).chain(overrides.iter().filter(|x| x.action != include));
+    for item in ordered {
+        let range = ip_tool::ip_to_number(item.start, ipv6).zip(ip_tool::ip_to_number(item.end, ipv6));
+        let changed = range.map(|range| {
+            let outside = extract::size(&extract::subtract(&[range], &ranges));
+            if item.action == include {
+                ranges = extract::union(&[ranges.as_slice(), &[range]].concat());
+                outside
+            } else {
+                ranges = extract::subtract(&ranges, &[range]);
+                extract::size(&[range]) - outside
+            }
+        });
+        effects.push(Effect { item: item.clone(), include: item.action == include, changed });
+    }
+    (ranges, effects)
+}
+
+#[cfg(test)]
+mod overrides_test {
+    use crate::{error::Error, overrides::*};
+
+    const CONTENT: &str = "# CDN 边缘节点
+1.0.1.0/24
+1.0.2.1 - 1.0.2.9  # 部分地址
+1.0.8.8
+2001:200::/32
+";
+
+    #[test]
+    fn parse_test() {
+        let list = parse(CONTENT, Action::Proxy, "force-proxy.txt").unwrap();
+        assert_eq!(4, list.len());
+        assert_eq!("force-proxy.txt:3", list[1].source);
+        assert_eq!("1.0.2.1 - 1.0.2.9", list[1].text);
+        assert_eq!(("1.0.1.0".parse::<IpAddr>().unwrap(), "1.0.1.255".parse::<IpAddr>().unwrap()), (list[0].start, list[0].end));
+        assert_eq!(list[2].start, list[2].end);
+        assert_eq!("2001:200:ffff:ffff:ffff:ffff:ffff:ffff".parse::<IpAddr>().unwrap(), list[3].end);
+        for line in ["1.0.1.0/33", "1.0.2.9-1.0.2.1", "1.0.0.1-::1", "256.0.0.1", "example.com"] {
+            match parse(line, Action::Direct, "a") {
+                Err(Error::Parse(x)) => assert!(x.contains("a:1"), "{}", x),
+                x => panic!("unexpected result {:?}", x),
+            }
+        }
+    }
+
+    #[test]
+    fn apply_test() {
+        let mut list = parse(CONTENT, Action::Proxy, "proxy").unwrap();
+        list.extend(parse("1.0.0.0/25\n1.0.4.0/24\n1.0.1.128/25", Action::Direct, "direct").unwrap());
+        // 1.0.0.0-1.0.0.255 和 1.0.1.0-1.0.1.255 已经走代理
+        let (ranges, effects) = apply(&[(16777216, 16777727)], &list, Action::Proxy, false);
+        assert_eq!(vec![(16777344, 16777599), (16777729, 16777737), (16779272, 16779272)], ranges);
+        let changed = effects.iter().map(|x| x.changed).collect::<Vec<_>>();
+        assert_eq!(vec![Some(0), Some(9), Some(1), None, Some(128), Some(0), Some(128)], changed);
+        assert_eq!("force-proxy 1.0.1.0/24 (proxy:2): no change", effects[0].to_string());
+        assert_eq!("force-direct 1.0.0.0/25 (direct:1): removed 128 addresses", effects[4].to_string());
+
+        // 直连模式下 force-direct 并入结果
+        let (ranges, effects) = apply(&[(0xffff_0100_0000, 0xffff_0100_00ff)], &list, Action::Direct, true);
+        assert_eq!(vec![(0xffff_0100_0000, 0xffff_0100_00ff), (0xffff_0100_0400, 0xffff_0100_04ff)], ranges);
+        let changed = effects.iter().map(|x| x.changed).collect::<Vec<_>>();
+        assert_eq!(vec![Some(0), Some(256), Some(128), Some(128), Some(0), Some(0), Some(0)], changed);
+        assert_eq!("force-proxy 2001:200::/32 (proxy:5): no change", effects[6].to_string());
+    }
+}
@@ -0,0 +1,282 @@
+use std::{collections::HashMap, env, path::PathBuf, time::Duration};
+
+use crate::{error::{self, Error}, extract, file_tool, guard, ip_tool, overrides, profile};
+
+// 命令行参数，`--download-dir /data` 与环境变量 `DOWNLOAD_DIR=/data` 等价，命令行优先
+pub struct Args {
+    pub command: Option<String>,
+    pub positional: Vec<String>,
+    options: HashMap<String, String>,
+}
+
+// 不带值的开关参数
+const FLAGS: [&str; 8] = ["bridge-gaps", "bridge-unassigned", "ipv6", "offline", "force", "strict-csv", "map-labels", "no-resolve"];
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Args {
+        let mut command = None;
+        let mut positional = vec![];
+        let mut options = HashMap::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(option) => {
+                    let (key, value) = match option.split_once('=') {
+                        Some((key, value)) => (key.to_string(), value.to_string()),
+                        None if FLAGS.contains(&option) => (option.to_string(), "true".to_string()),
+                        None => (option.to_string(), args.next().unwrap_or_default()),
+                    };
+                    options.insert(key, value);
+                },
+                None if command.is_none() && positional.is_empty() => command = Some(arg),
+                None => positional.push(arg),
+            }
+        }
+        Args {
+            command,
+            positional,
+            options,
+        }
+    }
+
+    // 环境变量不是 UTF-8 时返回错误
+    pub fn value(&self, key: &str) -> error::Result<Option<String>> {
+        if let Some(x) = self.options.get(key) {
+            return Ok(Some(x.to_string()));
+        }
+        let env_key = key.to_uppercase().replace('-', "_");
+        match env::var_os(&env_key) {
+            None => Ok(None),
+            Some(x) => x.into_string().map(Some).map_err(|_| Error::Config(format!("invalid parameter '{}': not valid utf-8", env_key))),
+        }
+    }
+
+    pub fn flag(&self, key: &str) -> error::Result<bool> {
+        Ok(match self.value(key)? {
+            None => false,
+            Some(x) => matches!(x.to_lowercase().as_str(), "1" | "true" | "yes" | "on"),
+        })
+    }
+}
+
+pub struct Param {
+    pub ip2location_token: Option<String>,
+    // 替代默认的下载地址，例如内网镜像
+    pub download_url: Option<String>,
+    pub download_dir: String,
+    // 不设置时直接从 ZIP 中读取 CSV，不解压到磁盘
+    pub unzip_dir: Option<String>,
+    pub output_dir: String,
+    // 替换输出文件前保存的历史版本个数，用于 rollback
+    pub keep_generations: usize,
+    pub ipv6: bool,
+    pub input: Option<String>,
+    pub offline: bool,
+    pub force: bool,
+    // 遇到无法解析的 CSV 行时报错，而不是跳过
+    pub strict_csv: bool,
+    pub download_options: file_tool::DownloadOptions,
+    pub exclude_reserved: Vec<ip_tool::SpecialPurpose>,
+    pub guardrails: guard::Guardrails,
+    // 每个 profile 生成一个输出文件，第一个用于发布前检查和统计
+    pub profiles: Vec<profile::Profile>,
+    // 强制走代理和强制直连的覆盖文件
+    pub override_files: Vec<(overrides::Action, PathBuf)>,
+    // profile 没有写 domains、no_resolve 时使用的域名规则文件和 no-resolve
+    pub domain_files: Vec<PathBuf>,
+    pub no_resolve: bool,
+    // 输出文件中 IPv6 地址的格式
+    pub ipv6_style: ip_tool::Ipv6Style,
+}
+
+// 解析形如 "30" 的秒数或次数
+pub fn parse_number<T: std::str::FromStr>(args: &Args, key: &str) -> error::Result<Option<T>> {
+    match args.value(key)? {
+        None => Ok(None),
+        Some(x) => x.trim().parse().map(Some).map_err(|_| {
+            Error::Config(format!("invalid parameter '{}': {}", key.to_uppercase().replace('-', "_"), x))
+        }),
+    }
+}
+
+// 逗号分隔的 IP 列表，IPv4 数据集中无法检查 IPv6 地址
+fn parse_ips(args: &Args, key: &str, ipv6: bool) -> error::Result<Vec<std::net::IpAddr>> {
+    let env_key = key.to_uppercase().replace('-', "_");
+    let mut list = vec![];
+    for x in args.value(key)?.unwrap_or_default().split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+        let ip = x.parse().map_err(|_| Error::Config(format!("invalid parameter '{}': {}", env_key, x)))?;
+        if ip_tool::ip_to_number(ip, ipv6).is_none() {
+            return Err(Error::Config(format!("invalid parameter '{}': {} can not be checked without IPV6", env_key, x)));
+        }
+        list.push(ip);
+    }
+    Ok(list)
+}
+
+pub fn param_analysis(args: &Args) -> error::Result<Param> {
+    let coalesce_policy = extract::CoalescePolicy {
+        bridge_gaps: args.flag("bridge-gaps")?,
+        bridge_unassigned: args.flag("bridge-unassigned")?,
+    };
+    let exclude_reserved = match args.value("exclude-reserved")? {
+        None => ip_tool::SpecialPurpose::ALL.to_vec(),
+        Some(x) => ip_tool::SpecialPurpose::parse_list(&x)
+            .ok_or_else(|| Error::Config(format!("invalid parameter 'EXCLUDE_RESERVED': {}", x)))?,
+    };
+    // 配置文件中 [source] 的值优先级低于命令行参数和环境变量
+    let config = match args.value("config")? {
+        None => profile::Config::default(),
+        Some(x) => profile::Config::read(std::path::Path::new(&x), coalesce_policy, &exclude_reserved)?,
+    };
+    let value = |key: &str| -> error::Result<Option<String>> { Ok(args.value(key)?.or_else(|| config.source(key))) };
+    let flag = |key: &str| -> error::Result<bool> {
+        match args.value(key)? {
+            Some(_) => args.flag(key),
+            None => Ok(config.source(key).as_deref() == Some("true")),
+        }
+    };
+    let ip2location_token = args.value("ip2location-token")?;
+    let download_url = args.value("download-url")?;
+    let download_dir = value("download-dir")?.unwrap_or("/data/ip-extract".to_string());
+    let unzip_dir = value("unzip-dir")?;
+    let output_dir = value("output-dir")?.unwrap_or("/data/ip-extract".to_string());
+    let keep_generations = parse_number(args, "keep-generations")?.unwrap_or(3);
+    let ipv6 = flag("ipv6")?;
+    let input = value("input")?;
+    let offline = flag("offline")?;
+    let force = args.flag("force")?;
+    let strict_csv = args.flag("strict-csv")?;
+    let mut download_options = file_tool::DownloadOptions::default();
+    if let Some(x) = parse_number(args, "download-retries")? {
+        download_options.retries = x;
+    }
+    download_options.sha256 = args.value("download-sha256")?;
+    download_options.proxy = args.value("download-proxy")?;
+    if let Some(x) = args.value("ca-bundle")? {
+        download_options.ca_bundles = x.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()).map(PathBuf::from).collect();
+    }
+    if let Some(x) = args.value("user-agent")? {
+        download_options.user_agent = x;
+    }
+    if let Some(x) = parse_number(args, "connect-timeout")? {
+        download_options.connect_timeout = Duration::from_secs(x);
+    }
+    if let Some(x) = parse_number(args, "read-timeout")? {
+        download_options.read_timeout = Duration::from_secs(x);
+    }
+    let guardrails = guard::Guardrails {
+        min_rows: parse_number(args, "min-rows")?.unwrap_or(0),
+        min_addresses: parse_number(args, "min-addresses")?.unwrap_or(0),
+        max_change_percent: parse_number(args, "max-change-percent")?,
+        required_in: parse_ips(args, "required-in", ipv6)?,
+        required_out: parse_ips(args, "required-out", ipv6)?,
+    };
+    let mut override_files = vec![];
+    for (key, action) in [("force-proxy", overrides::Action::Proxy), ("force-direct", overrides::Action::Direct)] {
+        let paths = value(key)?.unwrap_or_default();
+        override_files.extend(paths.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()).map(|x| (action, PathBuf::from(x))));
+    }
+    let domain_files = value("domains")?.unwrap_or_default()
+        .split(',').map(|x| x.trim()).filter(|x| !x.is_empty()).map(PathBuf::from).collect();
+    let no_resolve = flag("no-resolve")?;
+    let ipv6_style = match value("ipv6-style")? {
+        None => ip_tool::Ipv6Style::default(),
+        Some(x) => x.parse().map_err(|_| Error::Config(format!("invalid parameter 'IPV6_STYLE': {}", x)))?,
+    };
+    let mode = match args.value("mode")? {
+        None => profile::Mode::Proxy,
+        Some(x) => x.parse().map_err(|_| Error::Config(format!("invalid parameter 'MODE': {}", x)))?,
+    };
+    let countries = args.value("countries")?.unwrap_or("CN".to_string())
+        .split(',').map(|x| x.trim().to_string()).filter(|x| !x.is_empty()).collect::<Vec<_>>();
+    let profiles = if config.profiles.is_empty() {
+        if countries.is_empty() {
+            return Err(Error::Config("invalid parameter 'COUNTRIES': empty".to_string()));
+        }
+        profile::Profile::defaults(mode, &countries, coalesce_policy, &exclude_reserved)
+    } else {
+        config.profiles
+    };
+    Ok(Param {
+        ip2location_token,
+        download_url,
+        download_dir,
+        unzip_dir,
+        output_dir,
+        keep_generations,
+        ipv6,
+        input,
+        offline,
+        force,
+        strict_csv,
+        download_options,
+        exclude_reserved,
+        guardrails,
+        profiles,
+        override_files,
+        domain_files,
+        no_resolve,
+        ipv6_style,
+    })
+}
+
+#[cfg(test)]
+mod param_test {
+    use crate::param::*;
+
+    fn args(list: &[&str]) -> Args {
+        Args::parse(list.iter().map(|x| x.to_string()))
+    }
+
+    #[test]
+    fn parse_test() {
+        let args = args(&["lookup", "--unzip-dir", "/tmp/a", "1.1.1.1", "--bridge-gaps", "--output-dir=/tmp/b", "::1"]);
+        assert_eq!(Some("lookup".to_string()), args.command);
+        assert_eq!(vec!["1.1.1.1".to_string(), "::1".to_string()], args.positional);
+        assert_eq!(Some("/tmp/a".to_string()), args.value("unzip-dir").unwrap());
+        assert_eq!(Some("/tmp/b".to_string()), args.value("output-dir").unwrap());
+        assert!(args.flag("bridge-gaps").unwrap());
+        assert!(!args.flag("bridge-unassigned").unwrap());
+    }
+
+    #[test]
+    fn parse_without_command_test() {
+        let args = args(&["--download-dir", "/tmp/a"]);
+        assert_eq!(None, args.command);
+        assert!(args.positional.is_empty());
+        assert_eq!(Some("/tmp/a".to_string()), args.value("download-dir").unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_env_test() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        // 其他测试不会读取这个变量
+        env::set_var("IP_EXTRACT_NON_UTF8_TEST", OsStr::from_bytes(b"/tmp/\xff"));
+        match args(&[]).value("ip-extract-non-utf8-test") {
+            Err(Error::Config(x)) => assert!(x.contains("'IP_EXTRACT_NON_UTF8_TEST'"), "{}", x),
+            x => panic!("unexpected result {:?}", x),
+        }
+        env::remove_var("IP_EXTRACT_NON_UTF8_TEST");
+    }
+
+    #[test]
+    fn invalid_param_test() {
+        let e = param_analysis(&args(&["--read-timeout", "abc"])).err().unwrap();
+        assert_eq!("config error: invalid parameter 'READ_TIMEOUT': abc", e.to_string());
+        assert!(param_analysis(&args(&["--exclude-reserved", "private,nothing"])).is_err());
+        assert_eq!(7, param_analysis(&args(&["--download-retries", "7"])).unwrap().download_options.retries);
+        assert!(param_analysis(&args(&["--required-in", "1.1.1.1,nothing"])).is_err());
+        assert!(param_analysis(&args(&["--required-in", "1.1.1.1, 2001:200::1"])).is_err());
+        let param = param_analysis(&args(&["--ipv6", "--required-in", "1.1.1.1, 2001:200::1", "--max-change-percent", "2.5"])).unwrap();
+        assert_eq!(2, param.guardrails.required_in.len());
+        assert_eq!(Some(2.5), param.guardrails.max_change_percent);
+        assert!(param_analysis(&args(&["--mode", "both"])).is_err());
+        assert!(param_analysis(&args(&["--ipv6-style", "short"])).is_err());
+        assert_eq!(ip_tool::Ipv6Style::Padded, param_analysis(&args(&["--ipv6-style", "padded"])).unwrap().ipv6_style);
+        let param = param_analysis(&args(&["--mode", "direct", "--countries", "CN, HK"])).unwrap();
+        assert_eq!(vec!["CN".to_string(), "HK".to_string()], param.profiles[1].include);
+        assert_eq!(profile::Mode::Direct, param.profiles[1].mode);
+    }
+}
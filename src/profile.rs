@@ -0,0 +1,518 @@
+use std::{fs, net::IpAddr, path::{Path, PathBuf}};
+
+use crate::{error::{self, Error}, extract::{self, CoalescePolicy, IpLocation}, guard, ip_tool};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Format {
+    Proxifier,
+    // policy 为规则指向的策略组
+    Clash { policy: String },
+    Surge,
+    SingBox,
+    List,
+    // 经过 gateway 的路由表，gateway 与数据集的地址族相同
+    Routes { gateway: IpAddr },
+}
+
+impl Format {
+    fn parse(name: &str, policy: Option<String>, gateway: Option<IpAddr>) -> Option<Format> {
+        match name {
+            "proxifier" => Some(Format::Proxifier),
+            "clash" => Some(Format::Clash { policy: policy.unwrap_or("auto".to_string()) }),
+            "surge" => Some(Format::Surge),
+            "sing-box" => Some(Format::SingBox),
+            "list" => Some(Format::List),
+            "routes" => Some(Format::Routes { gateway: gateway? }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    // 输出需要走代理的地址，去掉特殊用途地址
+    Proxy,
+    // 输出需要直连的地址，并上特殊用途地址
+    Direct,
+}
+
+impl std::str::FromStr for Mode {
+    type Err = String;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        match str {
+            "proxy" => Ok(Mode::Proxy),
+            "direct" => Ok(Mode::Direct),
+            x => Err(format!("unknown mode {}", x)),
+        }
+    }
+}
+
+// 一个输出文件：从数据集中选出哪些国家，怎样合并、增减地址段，用什么格式写到哪里
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub mode: Mode,
+    // 只选中这些国家，为空时选中全部
+    pub include: Vec<String>,
+    // 不选中这些国家
+    pub exclude: Vec<String>,
+    pub coalesce_policy: CoalescePolicy,
+    // 代理模式下从结果中去掉，直连模式下并入结果
+    pub exclude_reserved: Vec<ip_tool::SpecialPurpose>,
+    // 合并后依次并上 add、减去 remove、与 intersect 取交集，都是 CIDR
+    pub add: Vec<String>,
+    pub remove: Vec<String>,
+    pub intersect: Vec<String>,
+    // 域名规则文件，None 时沿用 DOMAINS；只用于 clash、surge、sing-box
+    pub domains: Option<Vec<PathBuf>>,
+    // None 时沿用 NO_RESOLVE
+    pub no_resolve: Option<bool>,
+    // None 时沿用 IPV6_STYLE
+    pub ipv6_style: Option<ip_tool::Ipv6Style>,
+    // 是否应用 FORCE_PROXY、FORCE_DIRECT，配置文件中默认不应用
+    pub overrides: bool,
+    // 发布前检查，没有写的项目沿用环境变量
+    pub guardrails: guard::ProfileGuardrails,
+    pub format: Format,
+    // OUTPUT_DIR 下的文件名
+    pub output: String,
+}
+
+// CIDR 对应的地址段，IPv6 数据集中的 IPv4 地址映射到 ::ffff:0:0/96
+fn cidr_range(cidr: &str, ipv6: bool) -> Option<(u128, u128)> {
+    if !cidr.contains(':') {
+        let (start, end) = ip_tool::cidr_to_range_ipv4(cidr)?;
+        let offset = if ipv6 { ip_tool::MAPPED } else { 0 };
+        return Some((start as u128 | offset, end as u128 | offset));
+    }
+    if ipv6 { ip_tool::cidr_to_range_ipv6(cidr) } else { None }
+}
+
+impl Profile {
+    // 没有配置文件时的输出：proxifier.txt 和 clash.txt，代理模式下排除 countries，直连模式下只选中 countries
+    pub fn defaults(mode: Mode, countries: &[String], coalesce_policy: CoalescePolicy, exclude_reserved: &[ip_tool::SpecialPurpose]) -> Vec<Profile> {
+        let (include, exclude) = match mode {
+            Mode::Proxy => (vec![], countries.to_vec()),
+            Mode::Direct => (countries.to_vec(), vec![]),
+        };
+        [("proxifier", Format::Proxifier), ("clash", Format::Clash { policy: "auto".to_string() })].map(|(name, format)| Profile {
+            name: name.to_string(),
+            mode,
+            include: include.clone(),
+            exclude: exclude.clone(),
+            coalesce_policy,
+            exclude_reserved: exclude_reserved.to_vec(),
+            add: vec![],
+            remove: vec![],
+            intersect: vec![],
+            domains: None,
+            no_resolve: None,
+            ipv6_style: None,
+            overrides: true,
+            guardrails: guard::ProfileGuardrails::default(),
+            format,
+            output: format!("{}.txt", name),
+        }).to_vec()
+    }
+
+    fn cidr_ranges(&self, list: &[String], ipv6: bool) -> error::Result<Vec<(u128, u128)>> {
+        list.iter().map(|x| cidr_range(x, ipv6).ok_or_else(|| {
+            Error::Config(format!("invalid profile '{}': {} can not be used with {} dataset", self.name, x, if ipv6 { "an ipv6" } else { "an ipv4" }))
+        })).collect()
+    }
+
+    pub fn ranges(&self, list: &[IpLocation], ipv6: bool) -> error::Result<Vec<(u128, u128)>> {
+        let selected = |code: &str| (self.include.is_empty() || self.include.iter().any(|x| x == code)) && !self.exclude.iter().any(|x| x == code);
+        let ranges = extract::coalesce_by(list, selected, &self.coalesce_policy)?;
+        let ranges = match self.mode {
+            Mode::Proxy => extract::exclude_special_purpose(&ranges, &self.exclude_reserved, ipv6),
+            // 局域网等地址同样直连
+            Mode::Direct => [ranges, extract::special_purpose_ranges(&self.exclude_reserved, ipv6)].concat(),
+        };
+        let ranges = extract::union(&[ranges, self.cidr_ranges(&self.add, ipv6)?].concat());
+        let mut ranges = extract::subtract(&ranges, &self.cidr_ranges(&self.remove, ipv6)?);
+        if !self.intersect.is_empty() {
+            let outside = extract::subtract(&[(0, u128::MAX)], &self.cidr_ranges(&self.intersect, ipv6)?);
+            ranges = extract::subtract(&ranges, &outside);
+        }
+        Ok(ranges)
+    }
+
+    // options.domains 为已经读取的域名规则，策略与 IP 规则相同
+    pub fn render(&self, ranges: &[(u128, u128)], ipv6: bool, options: &extract::RuleOptions) -> error::Result<String> {
+        if let Format::Routes { gateway } = &self.format {
+            // IPv6 数据集中的 IPv4 地址也以映射地址输出，只能经过 IPv6 网关
+            if gateway.is_ipv6() != ipv6 {
+                return Err(Error::Config(format!("invalid profile '{}': gateway {} can not be used with {} dataset", self.name, gateway, if ipv6 { "an ipv6" } else { "an ipv4" })));
+            }
+        }
+        let list = if ipv6 { extract::to_ipv6_list_style(ranges, options.ipv6_style) } else { extract::to_ipv4_list(ranges)? };
+        let direct = self.mode == Mode::Direct;
+        Ok(match &self.format {
+            Format::Proxifier => extract::format_proxifier_action(&list, direct),
+            Format::Clash { policy } => extract::format_clash_policy(&list, policy, direct, options),
+            Format::Surge => extract::format_surge(&list, options),
+            Format::SingBox => extract::format_sing_box(&list, options),
+            Format::List => extract::format_list(&list, options),
+            Format::Routes { gateway } => extract::format_routes(&list, *gateway, options),
+        })
+    }
+}
+
+// 配置文件：[source] 声明一次数据源，每个 [[profile]] 生成一个输出文件
+#[derive(Debug, Default)]
+pub struct Config {
+    source: toml::Table,
+    pub profiles: Vec<Profile>,
+}
+
+const SOURCE_KEYS: [&str; 11] = ["input", "ipv6", "download_dir", "unzip_dir", "output_dir", "offline", "force_proxy", "force_direct", "domains", "no_resolve", "ipv6_style"];
+const PROFILE_KEYS: [&str; 22] = [
+    "name", "mode", "include", "exclude", "bridge_gaps", "bridge_unassigned", "exclude_reserved", "add", "remove", "intersect", "domains", "no_resolve", "ipv6_style", "overrides",
+    "min_addresses", "max_change_percent", "required_in", "required_out", "format", "policy", "gateway", "output",
+];
+
+fn string_list(table: &toml::Table, key: &str) -> Option<Vec<String>> {
+    match table.get(key) {
+        None => Some(vec![]),
+        Some(toml::Value::Array(list)) => list.iter().map(|x| x.as_str().map(|x| x.trim().to_string())).collect(),
+        Some(_) => None,
+    }
+}
+
+impl Config {
+    pub fn read(path: &Path, coalesce_policy: CoalescePolicy, exclude_reserved: &[ip_tool::SpecialPurpose]) -> error::Result<Config> {
+        let content = fs::read_to_string(path).map_err(|e| Error::Config(format!("read config error {}, {}", path.display(), e)))?;
+        Config::parse(&content, coalesce_policy, exclude_reserved)
+    }
+
+    // profile 中没有写的合并选项和特殊用途地址沿用命令行参数和环境变量
+    pub fn parse(content: &str, coalesce_policy: CoalescePolicy, exclude_reserved: &[ip_tool::SpecialPurpose]) -> error::Result<Config> {
+        let mut table = content.parse::<toml::Table>().map_err(|e| Error::Config(format!("invalid config: {}", e)))?;
+        let source = match table.remove("source") {
+            None => toml::Table::new(),
+            Some(toml::Value::Table(x)) => x,
+            Some(_) => return Err(Error::Config("invalid config: [source] must be a table".to_string())),
+        };
+        if let Some(key) = source.keys().find(|x| !SOURCE_KEYS.contains(&x.as_str())) {
+            return Err(Error::Config(format!("invalid config: unknown key '{}' in [source]", key)));
+        }
+        // 数组是多个文件，与命令行参数一样用逗号连接；其它类型无法转换成参数
+        let invalid = |x: &toml::Value| match x {
+            toml::Value::String(_) | toml::Value::Boolean(_) => false,
+            toml::Value::Array(list) => list.iter().any(|x| !x.is_str()),
+            _ => true,
+        };
+        if let Some((key, _)) = source.iter().find(|(_, x)| invalid(x)) {
+            return Err(Error::Config(format!("invalid config: '{}' in [source] must be a string, boolean or array of strings", key)));
+        }
+        let list = match table.remove("profile") {
+            None => vec![],
+            Some(toml::Value::Array(x)) => x,
+            Some(_) => return Err(Error::Config("invalid config: profile must be an array of tables, use [[profile]]".to_string())),
+        };
+        if let Some(key) = table.keys().next() {
+            return Err(Error::Config(format!("invalid config: unknown key '{}'", key)));
+        }
+        let mut profiles: Vec<Profile> = vec![];
+        for (i, x) in list.into_iter().enumerate() {
+            let x = x.as_table().ok_or_else(|| Error::Config(format!("invalid config: profile {} must be a table", i + 1)))?;
+            let name = x.get("name").and_then(|x| x.as_str()).map(|x| x.to_string()).unwrap_or_else(|| format!("profile-{}", i + 1));
+            let invalid = |message: String| Error::Config(format!("invalid profile '{}': {}", name, message));
+            if let Some(key) = x.keys().find(|x| !PROFILE_KEYS.contains(&x.as_str())) {
+                return Err(invalid(format!("unknown key '{}'", key)));
+            }
+            let list = |key: &str| string_list(x, key).ok_or_else(|| invalid(format!("{} must be an array of strings", key)));
+            let flag = |key: &str, default: bool| match x.get(key) {
+                None => Ok(default),
+                Some(value) => value.as_bool().ok_or_else(|| invalid(format!("{} must be a boolean", key))),
+            };
+            let string = |key: &str| match x.get(key) {
+                None => Ok(None),
+                Some(value) => value.as_str().map(|x| Some(x.to_string())).ok_or_else(|| invalid(format!("{} must be a string", key))),
+            };
+            let exclude_reserved = match string("exclude_reserved")? {
+                None => exclude_reserved.to_vec(),
+                Some(x) => ip_tool::SpecialPurpose::parse_list(&x).ok_or_else(|| invalid(format!("invalid exclude_reserved {}", x)))?,
+            };
+            let mode = string("mode")?.map_or(Ok(Mode::Proxy), |x| x.parse()).map_err(invalid)?;
+            let format_name = string("format")?.ok_or_else(|| invalid("missing format".to_string()))?;
+            // 网关写入 ip -batch 文件，只接受地址
+            let gateway = match string("gateway")? {
+                None => None,
+                Some(x) => Some(x.parse::<IpAddr>().map_err(|_| invalid(format!("invalid gateway {}", x)))?),
+            };
+            let format = match (format_name.as_str(), gateway) {
+                ("routes", None) => return Err(invalid("routes format needs gateway".to_string())),
+                (name, gateway) => Format::parse(name, string("policy")?, gateway).ok_or_else(|| invalid(format!("unknown format {}", name)))?,
+            };
+            // 历史版本按文件名保存，只允许 OUTPUT_DIR 下的普通文件
+            let output = string("output")?.ok_or_else(|| invalid("missing output".to_string()))?;
+            if output.is_empty() || output.starts_with('.') || output.contains(['/', '\\']) {
+                return Err(invalid(format!("output must be a file name in OUTPUT_DIR: {}", output)));
+            }
+            let [add, remove, intersect] = ["add", "remove", "intersect"].map(list);
+            let (add, remove, intersect) = (add?, remove?, intersect?);
+            if let Some(x) = add.iter().chain(&remove).chain(&intersect).find(|x| cidr_range(x, true).is_none()) {
+                return Err(invalid(format!("invalid cidr {}", x)));
+            }
+            let domains = match x.get("domains") {
+                None => None,
+                Some(_) => Some(list("domains")?.into_iter().map(PathBuf::from).collect()),
+            };
+            let no_resolve = match x.get("no_resolve") {
+                None => None,
+                Some(value) => Some(value.as_bool().ok_or_else(|| invalid("no_resolve must be a boolean".to_string()))?),
+            };
+            let ipv6_style = match string("ipv6_style")? {
+                None => None,
+                Some(x) => Some(x.parse().map_err(invalid)?),
+            };
+            let ips = |key: &str| -> error::Result<Option<Vec<IpAddr>>> {
+                if x.get(key).is_none() {
+                    return Ok(None);
+                }
+                list(key)?.iter().map(|ip| ip.parse().map_err(|_| invalid(format!("invalid {} {}", key, ip)))).collect::<error::Result<_>>().map(Some)
+            };
+            let min_addresses = match x.get("min_addresses") {
+                None => None,
+                Some(value) => Some(value.as_integer().and_then(|x| u128::try_from(x).ok()).ok_or_else(|| invalid("min_addresses must be a non-negative integer".to_string()))?),
+            };
+            let max_change_percent = match x.get("max_change_percent") {
+                None => None,
+                Some(value) => Some(value.as_float().or(value.as_integer().map(|x| x as f64)).ok_or_else(|| invalid("max_change_percent must be a number".to_string()))?),
+            };
+            let guardrails = guard::ProfileGuardrails { min_addresses, max_change_percent, required_in: ips("required_in")?, required_out: ips("required_out")? };
+            let include = list("include")?;
+            if mode == Mode::Direct && include.is_empty() {
+                return Err(invalid("direct mode needs include".to_string()));
+            }
+            let profile = Profile {
+                mode,
+                include,
+                exclude: list("exclude")?,
+                coalesce_policy: CoalescePolicy {
+                    bridge_gaps: flag("bridge_gaps", coalesce_policy.bridge_gaps)?,
+                    bridge_unassigned: flag("bridge_unassigned", coalesce_policy.bridge_unassigned)?,
+                },
+                exclude_reserved,
+                add,
+                remove,
+                intersect,
+                domains,
+                no_resolve,
+                ipv6_style,
+                overrides: flag("overrides", false)?,
+                guardrails,
+                format,
+                output,
+                name,
+            };
+            if let Some(other) = profiles.iter().find(|x| x.name == profile.name || x.output == profile.output) {
+                return Err(Error::Config(format!("invalid profile '{}': same name or output as profile '{}'", profile.name, other.name)));
+            }
+            profiles.push(profile);
+        }
+        Ok(Config { source, profiles })
+    }
+
+    // [source] 中的值，key 与命令行参数相同
+    pub fn source(&self, key: &str) -> Option<String> {
+        match self.source.get(&key.replace('-', "_"))? {
+            toml::Value::String(x) => Some(x.to_string()),
+            toml::Value::Array(list) => Some(list.iter().filter_map(|x| x.as_str()).collect::<Vec<_>>().join(",")),
+            x => Some(x.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod profile_test {
+    use crate::{domain, error::Error, extract::{row, CoalescePolicy, RuleOptions}, ip_tool::SpecialPurpose, profile::*};
+
+    const CONFIG: &str = r#"
+[source]
+input = "/data/IP2LOCATION-LITE-DB1.IPV6.CSV.ZIP"
+ipv6 = true
+
+[[profile]]
+name = "non-cn"
+exclude = ["CN"]
+overrides = true
+format = "proxifier"
+output = "proxifier.txt"
+
+[[profile]]
+name = "jp"
+include = ["JP"]
+bridge_gaps = true
+exclude_reserved = "none"
+add = ["1.0.8.0/24"]
+remove = ["1.0.4.0/23"]
+format = "clash"
+policy = "JP"
+output = "clash-jp.txt"
+
+[[profile]]
+name = "cn-router"
+include = ["CN"]
+intersect = ["1.0.0.0/16"]
+format = "list"
+output = "cn.txt"
+"#;
+
+    #[test]
+    fn parse_test() {
+        let config = Config::parse(CONFIG, CoalescePolicy::default(), &SpecialPurpose::ALL).unwrap();
+        assert_eq!(Some("/data/IP2LOCATION-LITE-DB1.IPV6.CSV.ZIP".to_string()), config.source("input"));
+        assert_eq!(Some("true".to_string()), config.source("ipv6"));
+        assert_eq!(None, config.source("download-dir"));
+        assert_eq!(3, config.profiles.len());
+        let jp = &config.profiles[1];
+        assert_eq!(vec!["JP".to_string()], jp.include);
+        assert!(jp.coalesce_policy.bridge_gaps);
+        assert!(jp.exclude_reserved.is_empty());
+        assert_eq!(Format::Clash { policy: "JP".to_string() }, jp.format);
+        assert_eq!(SpecialPurpose::ALL.to_vec(), config.profiles[0].exclude_reserved);
+        // 覆盖只用于写了 overrides = true 的 profile，默认 profile 都使用覆盖
+        assert!(config.profiles[0].overrides);
+        assert!(!jp.overrides);
+        assert!(Profile::defaults(Mode::Proxy, &[], CoalescePolicy::default(), &[]).iter().all(|x| x.overrides));
+        // 没有写的检查项目沿用环境变量
+        assert_eq!(None, jp.guardrails.required_in);
+        let config = Config::parse("[[profile]]\nrequired_in = [\"1.0.8.1\"]\nrequired_out = []\nmin_addresses = 256\nmax_change_percent = 5\nformat = \"list\"\noutput = \"a.txt\"", CoalescePolicy::default(), &[]).unwrap();
+        let guardrails = &config.profiles[0].guardrails;
+        assert_eq!(Some(vec!["1.0.8.1".parse().unwrap()]), guardrails.required_in);
+        assert_eq!(Some(vec![]), guardrails.required_out);
+        assert_eq!(Some(256), guardrails.min_addresses);
+        assert_eq!(Some(5.0), guardrails.max_change_percent);
+        assert!(Config::parse("", CoalescePolicy::default(), &[]).unwrap().profiles.is_empty());
+        // 文件列表可以写成数组，与逗号分隔的参数相同
+        let config = Config::parse("[source]\ndomains = [\"cn.txt\", \"gfw.txt\"]\nforce_proxy = \"a.txt,b.txt\"", CoalescePolicy::default(), &[]).unwrap();
+        assert_eq!(Some("cn.txt,gfw.txt".to_string()), config.source("domains"));
+        assert_eq!(Some("a.txt,b.txt".to_string()), config.source("force-proxy"));
+    }
+
+    #[test]
+    fn invalid_config_test() {
+        let invalid = |content: &str| match Config::parse(content, CoalescePolicy::default(), &[]) {
+            Err(Error::Config(x)) => x,
+            x => panic!("unexpected result {:?}", x),
+        };
+        assert!(invalid("[[profile]]\nname = \"a\"\nformat = \"clash\"\noutput = \"a.txt\"\ncountry = [\"CN\"]").contains("unknown key 'country'"));
+        assert!(invalid("[[profile]]\nname = \"a\"\nformat = \"html\"\noutput = \"a.txt\"").contains("unknown format html"));
+        assert!(invalid("[[profile]]\nname = \"a\"\nformat = \"list\"\noutput = \"../a.txt\"").contains("file name"));
+        assert!(invalid("[[profile]]\nname = \"a\"\nformat = \"list\"\noutput = \"a.txt\"\nadd = [\"1.0.0.0/33\"]").contains("invalid cidr"));
+        assert!(invalid("[[profile]]\nformat = \"list\"\noutput = \"a.txt\"\n[[profile]]\nformat = \"surge\"\noutput = \"a.txt\"").contains("same name or output"));
+        assert!(invalid("[[profile]]\nrequired_in = [\"1.0.0.256\"]\nformat = \"list\"\noutput = \"a.txt\"").contains("invalid required_in 1.0.0.256"));
+        assert!(invalid("[[profile]]\nmin_addresses = -1\nformat = \"list\"\noutput = \"a.txt\"").contains("min_addresses must be"));
+        assert!(invalid("[[profile]]\noverrides = \"yes\"\nformat = \"list\"\noutput = \"a.txt\"").contains("overrides must be a boolean"));
+        assert!(invalid("[source]\ntoken = \"a\"").contains("unknown key 'token'"));
+        assert!(invalid("[source]\ndomains = [[\"cn.txt\"]]").contains("'domains' in [source]"));
+        assert!(invalid("[source]\nforce_proxy = { path = \"a.txt\" }").contains("'force_proxy' in [source]"));
+        assert!(invalid("[[profile").starts_with("invalid config"));
+    }
+
+    #[test]
+    fn ranges_test() {
+        let list = [
+            row(16777216, 16777471, "JP"),
+            row(16777472, 16778239, "CN"),
+            row(16778240, 16779263, "JP"),
+            row(16779264, 16779519, "US"),
+            row(16780288, 16780543, "JP"),
+        ];
+        let config = Config::parse(CONFIG, CoalescePolicy::default(), &SpecialPurpose::ALL).unwrap();
+        let ranges = |i: usize| config.profiles[i].ranges(&list, false).unwrap();
+        assert_eq!(vec![(16777216, 16777471), (16778240, 16779519), (16780288, 16780543)], ranges(0));
+        // 并上 1.0.8.0/24 后与 1.0.4.0/22 合并，再减去 1.0.4.0/23
+        assert_eq!(vec![(16777216, 16777471), (16778752, 16779519), (16780288, 16780543)], ranges(1));
+        assert_eq!(vec![(16777472, 16778239)], ranges(2));
+
+        let jp = &config.profiles[1];
+        let content = jp.render(&ranges(1), false, &RuleOptions::default()).unwrap();
+        assert!(content.contains("  - IP-CIDR,1.0.0.0/24,JP\n"), "{}", content);
+        assert!(content.ends_with("  - MATCH,DIRECT\n"));
+        assert_eq!("1.0.1.0/24\n1.0.2.0/23\n", config.profiles[2].render(&ranges(2), false, &RuleOptions::default()).unwrap());
+
+        // IPv6 数据集中 IPv4 的 CIDR 映射到 ::ffff:0:0/96
+        let mapped = list.iter().map(|x| row(x.ip_start.parse::<u128>().unwrap() | 0xffff_0000_0000, x.ip_end.parse::<u128>().unwrap() | 0xffff_0000_0000, &x.country_code)).collect::<Vec<_>>();
+        let ranges = jp.ranges(&mapped, true).unwrap();
+        assert_eq!((0xffff_0100_0600, 0xffff_0100_08ff), ranges[1]);
+        let surge = Profile { format: Format::Surge, ..jp.clone() }.render(&[(0xffff_0100_0000, 0xffff_0100_00ff), (0x2001_0200 << 96, (0x2001_0200 << 96) + 0xffff)], true, &RuleOptions::default()).unwrap();
+        assert_eq!("IP-CIDR,1.0.0.0/24\nIP-CIDR6,2001:200::/112\n", surge);
+        let expanded = RuleOptions { ipv6_style: ip_tool::Ipv6Style::Expanded, ..Default::default() };
+        let list = Profile { format: Format::List, ..jp.clone() }.render(&[(0x2001_0200 << 96, (0x2001_0200 << 96) + 0xffff)], true, &expanded).unwrap();
+        assert_eq!("2001:200:0:0:0:0:0:0/112\n", list);
+        // 跨过 ::ffff:0:0/96 边界的地址段按 IPv6 输出
+        let list = Profile { format: Format::List, ..jp.clone() }.render(&[(0xffff_ffff_ff00, 0x1_0000_0000_00ff)], true, &RuleOptions::default()).unwrap();
+        assert_eq!("::ffff:255.255.255.0/120\n::1:0:0:0/120\n", list);
+        let sing_box = Profile { format: Format::SingBox, ..jp.clone() }.render(&[(16777216, 16777471)], false, &RuleOptions::default()).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&sing_box).unwrap();
+        assert_eq!("1.0.0.0/24", value["rules"][0]["ip_cidr"][0]);
+    }
+
+    #[test]
+    fn direct_test() {
+        let list = [row(16777216, 16777471, "JP"), row(16777472, 16778239, "CN"), row(16778240, 16779263, "US")];
+        let defaults = Profile::defaults(Mode::Direct, &["CN".to_string()], CoalescePolicy::default(), &[SpecialPurpose::Private]);
+        let ranges = defaults[0].ranges(&list, false).unwrap();
+        // CN 加上 10/8、172.16/12、192.168/16
+        assert_eq!(vec![(16777472, 16778239), (167772160, 184549375), (2886729728, 2887778303), (3232235520, 3232301055)], ranges);
+        let proxifier = defaults[0].render(&ranges[..1], false, &RuleOptions::default()).unwrap();
+        assert!(proxifier.contains("<Action type=\"Direct\" />"), "{}", proxifier);
+        assert!(proxifier.contains("<Name>IP-DIRECT-1</Name>"));
+        assert_eq!("  - GEOIP,LAN,DIRECT\n  - IP-CIDR,1.0.1.0/24,DIRECT\n  - IP-CIDR,1.0.2.0/23,DIRECT\n  - MATCH,auto\n", defaults[1].render(&ranges[..1], false, &RuleOptions::default()).unwrap());
+        let routes = Profile { format: Format::Routes { gateway: "192.168.1.1".parse().unwrap() }, ..defaults[0].clone() };
+        assert_eq!("route replace 1.0.1.0/24 via 192.168.1.1\nroute replace 1.0.2.0/23 via 192.168.1.1\n", routes.render(&ranges[..1], false, &RuleOptions::default()).unwrap());
+        // 网关与数据集的地址族不同
+        assert!(routes.render(&[(0xffff_0100_0100, 0xffff_0100_01ff)], true, &RuleOptions::default()).unwrap_err().to_string().contains("can not be used with an ipv6 dataset"));
+        let routes6 = Profile { format: Format::Routes { gateway: "fe80::1".parse().unwrap() }, ..defaults[0].clone() };
+        assert_eq!("route replace 2001:200::/23 via fe80::1\n", routes6.render(&[(0x2001_0200 << 96, (0x2001_0400 << 96) - 1)], true, &RuleOptions::default()).unwrap());
+        assert!(routes6.render(&ranges[..1], false, &RuleOptions::default()).is_err());
+
+        let proxy = Profile::defaults(Mode::Proxy, &["CN".to_string()], CoalescePolicy::default(), &[]);
+        assert!(proxy[0].render(&[(16777216, 16777471)], false, &RuleOptions::default()).unwrap().contains("<Action type=\"Proxy\">100</Action>"));
+
+        let invalid = |content: &str| Config::parse(content, CoalescePolicy::default(), &[]).unwrap_err().to_string();
+        assert!(invalid("[[profile]]\nmode = \"direct\"\nformat = \"list\"\noutput = \"a.txt\"").contains("needs include"));
+        assert!(invalid("[[profile]]\ninclude = [\"CN\"]\nformat = \"routes\"\noutput = \"a.txt\"").contains("needs gateway"));
+        assert!(invalid("[[profile]]\nformat = \"routes\"\ngateway = \"192.168.1.1\\nroute flush table main\"\noutput = \"a.txt\"").contains("invalid gateway"));
+        assert!(invalid("[[profile]]\nmode = \"both\"\nformat = \"list\"\noutput = \"a.txt\"").contains("unknown mode both"));
+    }
+
+    #[test]
+    fn domain_test() {
+        let config = Config::parse("[[profile]]\nexclude = [\"CN\"]\ndomains = [\"a.txt\"]\nno_resolve = true\nformat = \"clash\"\noutput = \"a.txt\"", CoalescePolicy::default(), &[]).unwrap();
+        let profile = &config.profiles[0];
+        assert_eq!(Some(vec![PathBuf::from("a.txt")]), profile.domains);
+        assert_eq!(Some(true), profile.no_resolve);
+        let domains = domain::parse("example.com\nkeyword:google\nfull:www.example.net", "a.txt").unwrap();
+        let options = RuleOptions { domains: &domains, no_resolve: true, ..Default::default() };
+        // 域名规则在 GEOIP 和 IP-CIDR 之前，no-resolve 在策略之后
+        assert_eq!("  - DOMAIN-SUFFIX,example.com,auto
+  - DOMAIN-KEYWORD,google,auto
+  - DOMAIN,www.example.net,auto
+  - GEOIP,LAN,DIRECT,no-resolve
+  - IP-CIDR,1.0.0.0/24,auto,no-resolve
+  - MATCH,DIRECT
+", profile.render(&[(16777216, 16777471)], false, &options).unwrap());
+        let surge = Profile { format: Format::Surge, ..profile.clone() }.render(&[(16777216, 16777471)], false, &options).unwrap();
+        assert_eq!("DOMAIN-SUFFIX,example.com\nDOMAIN-KEYWORD,google\nDOMAIN,www.example.net\nIP-CIDR,1.0.0.0/24,no-resolve\n", surge);
+        let sing_box = Profile { format: Format::SingBox, ..profile.clone() }.render(&[(16777216, 16777471)], false, &options).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&sing_box).unwrap();
+        assert_eq!(serde_json::json!(["example.com"]), value["rules"][0]["domain_suffix"]);
+        assert_eq!(serde_json::json!(["google"]), value["rules"][0]["domain_keyword"]);
+        assert_eq!(serde_json::json!(["www.example.net"]), value["rules"][0]["domain"]);
+        assert_eq!("1.0.0.0/24", value["rules"][1]["ip_cidr"][0]);
+        // 其余格式只有 IP 规则
+        let list = Profile { format: Format::List, ..profile.clone() }.render(&[(16777216, 16777471)], false, &options).unwrap();
+        assert_eq!("1.0.0.0/24\n", list);
+
+        let invalid = |content: &str| Config::parse(content, CoalescePolicy::default(), &[]).unwrap_err().to_string();
+        assert!(invalid("[[profile]]\ndomains = \"a.txt\"\nformat = \"list\"\noutput = \"a.txt\"").contains("domains must be an array"));
+        assert!(invalid("[[profile]]\nipv6_style = \"short\"\nformat = \"list\"\noutput = \"a.txt\"").contains("unknown ipv6 style short"));
+        assert!(invalid("[[profile]]\nno_resolve = \"yes\"\nformat = \"list\"\noutput = \"a.txt\"").contains("no_resolve must be a boolean"));
+    }
+}
@@ -0,0 +1,153 @@
+use std::{str::FromStr, time::Duration};
+
+use chrono::{DateTime, Local};
+
+// 定时任务，支持固定间隔（30m、6h、1d）和 cron 表达式（分 时 日 月 周，也可以带秒）
+pub enum Schedule {
+    Interval(Duration),
+    Cron(Box<cron::Schedule>),
+}
+
+impl Schedule {
+    pub fn parse(str: &str) -> Result<Schedule, String> {
+        let str = str.trim();
+        if let Some(interval) = parse_interval(str) {
+            if interval.is_zero() {
+                return Err("interval must be greater than 0".to_string());
+            }
+            return Ok(Schedule::Interval(interval));
+        }
+        let fields = str.split_whitespace().collect::<Vec<_>>();
+        let expression = match fields.len() {
+            5 => format!("0 {} {}", fields[..4].join(" "), day_of_week(fields[4])?),
+            6 | 7 => str.to_string(),
+            _ => return Err("expected an interval like 6h or a cron expression".to_string()),
+        };
+        let schedule = cron::Schedule::from_str(&expression).map_err(|e| e.to_string())?;
+        Ok(Schedule::Cron(Box::new(schedule)))
+    }
+
+    // 下一次运行的时间以及距离现在的时长
+    pub fn next(&self) -> (DateTime<Local>, Duration) {
+        self.next_after(Local::now())
+    }
+
+    pub fn next_after(&self, now: DateTime<Local>) -> (DateTime<Local>, Duration) {
+        match self {
+            Schedule::Interval(interval) => {
+                let next = now + chrono::Duration::from_std(*interval).unwrap_or(chrono::Duration::MAX);
+                (next, *interval)
+            },
+            Schedule::Cron(schedule) => match schedule.after(&now).next() {
+                Some(next) => (next, (next - now).to_std().unwrap_or_default()),
+                // 表达式不会再触发时（例如指定了过去的年份），一天后再检查
+                None => (now + chrono::Duration::days(1), Duration::from_secs(86400)),
+            },
+        }
+    }
+}
+
+// crontab 的星期为 0-7（0 和 7 都是周日），cron crate 为 1-7（周日为 1），数字展开后逐个转换，名称不变
+fn day_of_week(field: &str) -> Result<String, String> {
+    if field == "*" || field == "?" {
+        return Ok(field.to_string());
+    }
+    let invalid = || format!("invalid day of week {}", field);
+    let mut items = vec![];
+    let mut days = vec![];
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, Some(step.parse::<usize>().ok().filter(|&x| x > 0).ok_or_else(invalid)?)),
+            None => (item, None),
+        };
+        if range.chars().any(|x| x.is_ascii_alphabetic()) {
+            items.push(item.to_string());
+            continue;
+        }
+        let number = |x: &str| x.parse::<usize>().ok().filter(|&x| x <= 7).ok_or_else(invalid);
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (0, 6),
+            Some((start, end)) => (number(start)?, number(end)?),
+            // n/s 表示从 n 开始到周六
+            None if step.is_some() => (number(range)?, 6),
+            None => (number(range)?, number(range)?),
+        };
+        if start > end {
+            return Err(invalid());
+        }
+        days.extend((start..=end).step_by(step.unwrap_or(1)).map(|x| x % 7 + 1));
+    }
+    days.sort();
+    days.dedup();
+    items.splice(0..0, days.into_iter().map(|x| x.to_string()));
+    Ok(items.join(","))
+}
+
+fn parse_interval(str: &str) -> Option<Duration> {
+    let unit_index = str.find(|x: char| !x.is_ascii_digit()).unwrap_or(str.len());
+    let (number, unit) = str.split_at(unit_index);
+    let number = number.parse::<u64>().ok()?;
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return None,
+    };
+    Some(Duration::from_secs(number.checked_mul(seconds)?))
+}
+
+#[cfg(test)]
+mod schedule_test {
+    use std::time::Duration;
+
+    use chrono::{Local, TimeZone};
+
+    use crate::schedule::*;
+
+    #[test]
+    fn interval_test() {
+        let now = Local.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let schedule = Schedule::parse("6h").unwrap();
+        assert_eq!((now + chrono::Duration::hours(6), Duration::from_secs(6 * 3600)), schedule.next_after(now));
+        assert_eq!(Duration::from_secs(1800), Schedule::parse("30m").unwrap().next_after(now).1);
+        assert_eq!(Duration::from_secs(86400), Schedule::parse("1d").unwrap().next_after(now).1);
+        assert_eq!(Duration::from_secs(90), Schedule::parse("90").unwrap().next_after(now).1);
+        assert!(Schedule::parse("0h").is_err());
+        assert!(Schedule::parse("6w").is_err());
+    }
+
+    #[test]
+    fn cron_test() {
+        let now = Local.with_ymd_and_hms(2024, 1, 1, 5, 30, 0).unwrap();
+        let (next, delay) = Schedule::parse("0 4 * * *").unwrap().next_after(now);
+        assert_eq!(Local.with_ymd_and_hms(2024, 1, 2, 4, 0, 0).unwrap(), next);
+        assert_eq!(Duration::from_secs(22 * 3600 + 30 * 60), delay);
+        let (next, _) = Schedule::parse("0 0 4 3 * *").unwrap().next_after(now);
+        assert_eq!(Local.with_ymd_and_hms(2024, 1, 3, 4, 0, 0).unwrap(), next);
+        assert!(Schedule::parse("0 4 * *").is_err());
+        assert!(Schedule::parse("x 4 * * *").is_err());
+    }
+
+    #[test]
+    fn day_of_week_test() {
+        // 2024-01-06 为周六
+        let now = Local.with_ymd_and_hms(2024, 1, 6, 12, 0, 0).unwrap();
+        let next = |x: &str| Schedule::parse(x).unwrap().next_after(now).0;
+        let day = |d: u32, h: u32| Local.with_ymd_and_hms(2024, 1, d, h, 0, 0).unwrap();
+        // 周一到周五
+        assert_eq!(day(8, 3), next("0 3 * * 1-5"));
+        // 0 和 7 都是周日
+        assert_eq!(day(7, 3), next("0 3 * * 0"));
+        assert_eq!(day(7, 3), next("0 3 * * 7"));
+        assert_eq!(day(7, 3), next("0 3 * * 5-7"));
+        assert_eq!(day(9, 3), next("0 3 * * 2,4"));
+        assert_eq!(day(7, 3), next("0 3 * * */2"));
+        assert_eq!(day(8, 3), next("0 3 * * Mon-Fri"));
+        // 带秒的表达式仍按 cron crate 的规则
+        assert_eq!(day(7, 3), next("0 0 3 * * 1"));
+        assert!(Schedule::parse("0 3 * * 8").is_err());
+        assert!(Schedule::parse("0 3 * * 5-1").is_err());
+        assert!(Schedule::parse("0 3 * * */0").is_err());
+    }
+}
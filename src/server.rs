@@ -0,0 +1,320 @@
+use std::{collections::HashMap, convert::Infallible, fs, net::{IpAddr, SocketAddr}, path::PathBuf, sync::Arc, time::SystemTime};
+
+use hyper::{Body, Method, Request, Response, Server, StatusCode, header, service::{make_service_fn, service_fn}};
+use serde_json::{json, Value};
+
+use crate::{ip_tool, lookup::IpIndex};
+
+pub struct ServerState {
+    pub output_dir: PathBuf,
+    // 可以下载的文件，即各 profile 的输出文件名，输出目录下的其它文件不对外提供
+    pub files: Vec<String>,
+    pub index: Option<IpIndex>,
+}
+
+#[tokio::main]
+pub async fn serve(addr: SocketAddr, state: ServerState) -> Result<(), Box<dyn std::error::Error>> {
+    let state = Arc::new(state);
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(handle(&state, &request)) }
+            }))
+        }
+    });
+    println!("listening on http://{}", addr);
+    Server::bind(&addr).serve(make_service).await?;
+    Ok(())
+}
+
+pub fn handle(state: &ServerState, request: &Request<Body>) -> Response<Body> {
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return json_response(StatusCode::METHOD_NOT_ALLOWED, json!({ "error": "method not allowed" }));
+    }
+    let path = request.uri().path();
+    let query = query_map(request.uri().query());
+    match path.strip_prefix("/api/") {
+        Some(api) => match api_result(state, api, &query) {
+            Ok(value) => json_response(StatusCode::OK, value),
+            Err((status, message)) => json_response(status, json!({ "error": message })),
+        },
+        None => file_response(state, request, &path[1..]),
+    }
+}
+
+fn query_map(query: Option<&str>) -> HashMap<String, String> {
+    url::form_urlencoded::parse(query.unwrap_or_default().as_bytes()).into_owned().collect()
+}
+
+fn json_response(status: StatusCode, value: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json; charset=utf-8")
+        .body(Body::from(value.to_string()))
+        .unwrap()
+}
+
+type ApiResult = Result<Value, (StatusCode, String)>;
+
+fn required<'a>(query: &'a HashMap<String, String>, key: &str) -> Result<&'a str, (StatusCode, String)> {
+    query.get(key)
+        .map(|x| x.trim())
+        .ok_or_else(|| (StatusCode::BAD_REQUEST, format!("missing query parameter '{}'", key)))
+}
+
+fn invalid(key: &str, value: &str) -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, format!("invalid {}: {}", key, value))
+}
+
+// 默认按严格规则解析地址，lenient=true 时使用宽松模式
+fn parse_mode(query: &HashMap<String, String>) -> Result<ip_tool::ParseMode, (StatusCode, String)> {
+    match query.get("lenient").map(|x| x.as_str()) {
+        None | Some("false") => Ok(ip_tool::ParseMode::Strict),
+        Some("true") => Ok(ip_tool::ParseMode::Lenient),
+        Some(x) => Err(invalid("lenient", x)),
+    }
+}
+
+// 错误信息带上地址无法解析的原因
+fn invalid_ip(ip: &str, e: ip_tool::AddrError) -> (StatusCode, String) {
+    invalid("ip", &format!("{}, {}", ip, e))
+}
+
+fn api_result(state: &ServerState, api: &str, query: &HashMap<String, String>) -> ApiResult {
+    match api {
+        "ipv4-to-u32" => {
+            let ip = required(query, "ip")?;
+            let number = ip_tool::parse_ipv4(ip, parse_mode(query)?).map_err(|e| invalid_ip(ip, e))?;
+            Ok(json!({ "ip": ip, "number": number }))
+        },
+        "u32-to-ipv4" => {
+            let number = required(query, "number")?;
+            let ip = ip_tool::u32_to_ipv4(number.parse().map_err(|_| invalid("number", number))?);
+            Ok(json!({ "number": number, "ip": ip }))
+        },
+        // u128 超出 JSON 数字的安全范围，用字符串表示
+        "ipv6-to-u128" => {
+            let ip = required(query, "ip")?;
+            let number = ip_tool::parse_ipv6(ip, parse_mode(query)?).map_err(|e| invalid_ip(ip, e))?;
+            Ok(json!({ "ip": ip, "number": number.to_string() }))
+        },
+        "u128-to-ipv6" => {
+            let number = required(query, "number")?;
+            // style 为 canonical（默认）、expanded 或 padded
+            let style = match query.get("style") {
+                None => ip_tool::Ipv6Style::default(),
+                Some(x) => x.parse().map_err(|_| invalid("style", x))?,
+            };
+            let ip = ip_tool::format_ipv6(number.parse().map_err(|_| invalid("number", number))?, style);
+            Ok(json!({ "number": number, "ip": ip }))
+        },
+        "ipv4-to-ipv6" => {
+            let ip = required(query, "ip")?;
+            let number = ip_tool::parse_ipv4(ip, parse_mode(query)?).map_err(|e| invalid_ip(ip, e))?;
+            let ipv6 = ip_tool::u128_to_ipv6(number as u128 | 0xffff_0000_0000);
+            Ok(json!({ "ip": ip, "ipv6": ipv6 }))
+        },
+        "ipv6-to-ipv4" => {
+            let ip = required(query, "ip")?;
+            let number = ip_tool::parse_ipv6(ip, parse_mode(query)?).map_err(|e| invalid_ip(ip, e))?;
+            let ipv4 = ip_tool::u32_to_ipv4(number as u32);
+            Ok(json!({ "ip": ip, "ipv4": ipv4 }))
+        },
+        "cidr-to-range" => {
+            let cidr = required(query, "cidr")?;
+            if cidr.contains(':') {
+                let info = ip_tool::cidr_to_ipv6(cidr).ok_or_else(|| invalid("cidr", cidr))?;
+                // count 为 0 表示 2^128
+                let count = if info.count == 0 { "340282366920938463463374607431768211456".to_string() } else { info.count.to_string() };
+                Ok(json!({ "cidr": info.cidr, "ip_start": info.ip_start, "ip_end": info.ip_end, "mask": info.mask, "count": count }))
+            } else {
+                let info = ip_tool::cidr_to_ipv4(cidr).ok_or_else(|| invalid("cidr", cidr))?;
+                let count = if info.count == 0 { 1_u64 << 32 } else { info.count as u64 };
+                Ok(json!({ "cidr": info.cidr, "ip_start": info.ip_start, "ip_end": info.ip_end, "mask": info.mask, "count": count }))
+            }
+        },
+        "range-to-cidr" => {
+            let start = required(query, "start")?;
+            let end = required(query, "end")?;
+            let cidrs = if start.contains(':') || end.contains(':') {
+                ip_tool::ipv6_to_cidr(start, end)
+            } else {
+                ip_tool::ipv4_to_cidr(start, end)
+            };
+            let cidrs = cidrs.ok_or_else(|| invalid("range", &format!("{}-{}", start, end)))?;
+            Ok(json!({ "start": start, "end": end, "cidrs": cidrs }))
+        },
+        "lookup" => {
+            let index = state.index.as_ref()
+                .ok_or_else(|| (StatusCode::SERVICE_UNAVAILABLE, "lookup index is not loaded".to_string()))?;
+            let ip = required(query, "ip")?;
+            // aton=true 时 IPv4 地址可以使用 inet_aton 的写法，例如 0x7f.1、2130706433，结果中带上写法和标准写法
+            let conversion = match query.get("aton").map(|x| x.as_str()) {
+                None | Some("false") => None,
+                Some("true") => Some(ip_tool::convert(ip).map_err(|e| invalid_ip(ip, e))?),
+                Some(x) => return Err(invalid("aton", x)),
+            };
+            let addr = match &conversion {
+                None => ip.parse::<IpAddr>().map_err(|_| invalid("ip", ip))?,
+                Some(x) => x.address.parse::<IpAddr>().map_err(|_| invalid("ip", ip))?,
+            };
+            let x = index.lookup(addr).ok_or_else(|| (StatusCode::NOT_FOUND, format!("ip not found: {}", ip)))?;
+            let mut value = json!({ "ip": ip, "country_code": x.country_code, "country_name": x.country_name });
+            if let Some(conversion) = conversion {
+                value["address"] = json!(conversion.address);
+                value["notation"] = json!(conversion.notation);
+                value["notations"] = conversion.notations.into_iter().map(|(name, text)| (name, json!(text))).collect();
+            }
+            Ok(value)
+        },
+        _ => Err((StatusCode::NOT_FOUND, format!("unknown api: {}", api))),
+    }
+}
+
+fn file_response(state: &ServerState, request: &Request<Body>, name: &str) -> Response<Body> {
+    let not_found = || json_response(StatusCode::NOT_FOUND, json!({ "error": "not found" }));
+    // 只提供 profile 的输出文件，地图、统计和其它文件不对外提供
+    if !state.files.iter().any(|x| x == name) {
+        return not_found();
+    }
+    let path = state.output_dir.join(name);
+    let metadata = match fs::metadata(&path) {
+        Ok(x) if x.is_file() => x,
+        _ => return not_found(),
+    };
+    let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    let modified_nanos = modified.duration_since(SystemTime::UNIX_EPOCH).map(|x| x.as_nanos()).unwrap_or_default();
+    let etag = format!("\"{:x}-{:x}\"", metadata.len(), modified_nanos);
+    let last_modified = httpdate::fmt_http_date(modified);
+
+    let headers = request.headers();
+    let not_modified = match headers.get(header::IF_NONE_MATCH) {
+        Some(x) => x.to_str().map(|x| x.split(',').any(|x| x.trim() == etag || x.trim() == "*")).unwrap_or(false),
+        None => headers.get(header::IF_MODIFIED_SINCE)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| httpdate::parse_http_date(x).ok())
+            .map(|since| httpdate::parse_http_date(&last_modified).map(|x| x <= since).unwrap_or(false))
+            .unwrap_or(false),
+    };
+    let builder = Response::builder()
+        .header(header::ETAG, &etag)
+        .header(header::LAST_MODIFIED, &last_modified)
+        .header(header::CACHE_CONTROL, "no-cache");
+    if not_modified {
+        return builder.status(StatusCode::NOT_MODIFIED).body(Body::empty()).unwrap();
+    }
+    let content = match fs::read(&path) {
+        Ok(x) => x,
+        Err(_) => return not_found(),
+    };
+    let content_type = if name.ends_with(".json") { "application/json; charset=utf-8" } else { "text/plain; charset=utf-8" };
+    let builder = builder
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_LENGTH, content.len());
+    if request.method() == Method::HEAD {
+        return builder.body(Body::empty()).unwrap();
+    }
+    builder.body(Body::from(content)).unwrap()
+}
+
+#[cfg(test)]
+mod server_test {
+    use std::{env, fs};
+
+    use hyper::{Body, Request, StatusCode, header};
+    use serde_json::Value;
+
+    use crate::{extract::IpLocation, lookup::IpIndex, server::*};
+
+    fn state(name: &str) -> ServerState {
+        let output_dir = env::temp_dir().join(format!("ip-extract-server-test-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&output_dir).unwrap();
+        fs::write(output_dir.join("clash.txt"), "  - MATCH,DIRECT\n").unwrap();
+        let index = IpIndex::new(vec![IpLocation {
+            ip_start: "16777216".to_string(),
+            ip_end: "16777471".to_string(),
+            country_code: "US".to_string(),
+            country_name: "United States of America".to_string(),
+        }]).unwrap();
+        fs::write(output_dir.join("map.png"), "png").unwrap();
+        ServerState { output_dir, files: vec!["clash.txt".to_string(), "missing.txt".to_string()], index: Some(index) }
+    }
+
+    fn get(state: &ServerState, uri: &str) -> Response<Body> {
+        handle(state, &Request::get(uri).body(Body::empty()).unwrap())
+    }
+
+    async fn json_body(response: Response<Body>) -> Value {
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn convert_api_test() {
+        let state = state("convert");
+        let response = get(&state, "/api/ipv4-to-u32?ip=1.0.0.0");
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(16777216, json_body(response).await["number"]);
+        assert_eq!("1.0.0.0", json_body(get(&state, "/api/u32-to-ipv4?number=16777216")).await["ip"]);
+        assert_eq!("1", json_body(get(&state, "/api/ipv6-to-u128?ip=%3A%3A1")).await["number"]);
+        assert_eq!("::1", json_body(get(&state, "/api/u128-to-ipv6?number=1")).await["ip"]);
+        assert_eq!("0:0:0:0:0:0:0:1", json_body(get(&state, "/api/u128-to-ipv6?number=1&style=expanded")).await["ip"]);
+        assert_eq!("::ffff:1.2.3.4", json_body(get(&state, "/api/ipv4-to-ipv6?ip=1.2.3.4")).await["ipv6"]);
+        assert_eq!(4294967296_u64, json_body(get(&state, "/api/cidr-to-range?cidr=0.0.0.0/0")).await["count"]);
+        assert_eq!("103.165.87.255", json_body(get(&state, "/api/cidr-to-range?cidr=103.165.84.5/22")).await["ip_end"]);
+        let value = json_body(get(&state, "/api/range-to-cidr?start=192.168.6.73&end=192.168.6.75")).await;
+        assert_eq!(serde_json::json!(["192.168.6.73/32", "192.168.6.74/31"]), value["cidrs"]);
+        assert_eq!(StatusCode::BAD_REQUEST, get(&state, "/api/ipv4-to-u32?ip=x").status());
+        assert_eq!(StatusCode::BAD_REQUEST, get(&state, "/api/ipv4-to-u32").status());
+        let response = get(&state, "/api/ipv4-to-u32?ip=256.1.1.1");
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        assert_eq!("invalid ip: 256.1.1.1, octet '256' is greater than 255", json_body(response).await["error"]);
+        assert_eq!(StatusCode::BAD_REQUEST, get(&state, "/api/ipv4-to-u32?ip=01.2.3.4").status());
+        assert_eq!(16909060, json_body(get(&state, "/api/ipv4-to-u32?ip=01.2.3.4&lenient=true")).await["number"]);
+        assert_eq!(StatusCode::BAD_REQUEST, get(&state, "/api/ipv6-to-u128?ip=1%3A%3A2%3A%3A3").status());
+        assert_eq!("281470698652420", json_body(get(&state, "/api/ipv6-to-u128?ip=1.2.3.4&lenient=true")).await["number"]);
+        assert_eq!(StatusCode::NOT_FOUND, get(&state, "/api/unknown").status());
+    }
+
+    #[tokio::test]
+    async fn lookup_api_test() {
+        let state = state("lookup");
+        assert_eq!("US", json_body(get(&state, "/api/lookup?ip=1.0.0.1")).await["country_code"]);
+        assert_eq!(StatusCode::NOT_FOUND, get(&state, "/api/lookup?ip=8.8.8.8").status());
+        assert_eq!(StatusCode::BAD_REQUEST, get(&state, "/api/lookup?ip=0x1.1").status());
+        let value = json_body(get(&state, "/api/lookup?ip=0x1.1&aton=true")).await;
+        assert_eq!("US", value["country_code"]);
+        assert_eq!("1.0.0.1", value["address"]);
+        assert_eq!("two-part-hex", value["notation"]);
+        assert_eq!("16777217", value["notations"]["integer-decimal"]);
+        assert_eq!(StatusCode::BAD_REQUEST, get(&state, "/api/lookup?ip=1.2.3.4.5&aton=true").status());
+        let state = ServerState { index: None, ..state };
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, get(&state, "/api/lookup?ip=1.0.0.1").status());
+    }
+
+    #[tokio::test]
+    async fn file_test() {
+        let state = state("file");
+        let response = get(&state, "/clash.txt");
+        assert_eq!(StatusCode::OK, response.status());
+        let etag = response.headers()[header::ETAG].clone();
+        let last_modified = response.headers()[header::LAST_MODIFIED].clone();
+        assert_eq!(&b"  - MATCH,DIRECT\n"[..], &hyper::body::to_bytes(response.into_body()).await.unwrap()[..]);
+
+        let request = Request::get("/clash.txt").header(header::IF_NONE_MATCH, etag).body(Body::empty()).unwrap();
+        assert_eq!(StatusCode::NOT_MODIFIED, handle(&state, &request).status());
+        let request = Request::get("/clash.txt").header(header::IF_MODIFIED_SINCE, last_modified).body(Body::empty()).unwrap();
+        assert_eq!(StatusCode::NOT_MODIFIED, handle(&state, &request).status());
+        let request = Request::get("/clash.txt").header(header::IF_NONE_MATCH, "\"0-0\"").body(Body::empty()).unwrap();
+        assert_eq!(StatusCode::OK, handle(&state, &request).status());
+
+        assert_eq!(StatusCode::NOT_FOUND, get(&state, "/missing.txt").status());
+        assert_eq!(StatusCode::NOT_FOUND, get(&state, "/map.png").status());
+        assert_eq!(StatusCode::NOT_FOUND, get(&state, "/.state").status());
+        assert_eq!(StatusCode::NOT_FOUND, get(&state, "/../clash.txt").status());
+        assert_eq!(StatusCode::NOT_FOUND, get(&state, "/").status());
+    }
+}
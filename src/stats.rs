@@ -0,0 +1,262 @@
+use std::collections::BTreeMap;
+
+use serde_json::json;
+
+use crate::{diff, error, extract::{self, IpLocation}, ip_tool};
+
+// 2000::/3，IANA 分配的 IPv6 全球单播地址
+const GLOBAL_UNICAST: (u128, u128) = (0x2000 << 112, (0x4000 << 112) - 1);
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Count {
+    pub ipv4_ranges: usize,
+    pub ipv4_addresses: u128,
+    pub ipv6_ranges: usize,
+    pub ipv6_addresses: u128,
+}
+
+impl Count {
+    // IPv6 数据集中 ::ffff:0:0/96 内的部分算作 IPv4
+    fn add(&mut self, start: u128, end: u128, ipv6: bool) {
+        if !ipv6 {
+            self.ipv4_ranges += 1;
+            self.ipv4_addresses += end - start + 1;
+            return;
+        }
+        let (mapped_start, mapped_end) = (start.max(ip_tool::MAPPED), end.min(ip_tool::MAPPED_END));
+        if mapped_start <= mapped_end {
+            self.ipv4_ranges += 1;
+            self.ipv4_addresses += mapped_end - mapped_start + 1;
+        }
+        let rest = extract::subtract(&[(start, end)], &[(ip_tool::MAPPED, ip_tool::MAPPED_END)]);
+        if !rest.is_empty() {
+            self.ipv6_ranges += 1;
+            self.ipv6_addresses = self.ipv6_addresses.saturating_add(extract::size(&rest));
+        }
+    }
+}
+
+// 数据集中可路由的地址段：IPv4 为全部地址去掉特殊用途地址，IPv6 数据集另外加上 2000::/3 去掉特殊用途地址
+fn routable_ranges(ipv6: bool) -> Vec<(u128, u128)> {
+    let special_ipv4 = ip_tool::special_purpose_ranges_ipv4(&ip_tool::SpecialPurpose::ALL).into_iter()
+        .map(|(start, end)| (start as u128, end as u128))
+        .collect::<Vec<_>>();
+    let ipv4 = extract::subtract(&[(0, u32::MAX as u128)], &special_ipv4);
+    if !ipv6 {
+        return ipv4;
+    }
+    let special_ipv6 = ip_tool::special_purpose_ranges_ipv6(&ip_tool::SpecialPurpose::ALL);
+    ipv4.into_iter()
+        .map(|(start, end)| (start | ip_tool::MAPPED, end | ip_tool::MAPPED))
+        .chain(extract::subtract(&[GLOBAL_UNICAST], &special_ipv6))
+        .collect()
+}
+
+// 可路由的 IPv4 和 IPv6 地址个数
+pub fn routable() -> (u128, u128) {
+    let ipv4 = extract::size(&routable_ranges(false));
+    (ipv4, extract::size(&routable_ranges(true)) - ipv4)
+}
+
+pub struct Stats {
+    pub countries: BTreeMap<String, Count>,
+    // 汇总行：可路由、特殊用途、选中（输出中）的地址
+    pub summary: Vec<(String, Count)>,
+    // 每个输出文件的规则条数
+    pub rules: Vec<(String, usize)>,
+    routable: (u128, u128),
+    // 与 countries、summary 对应，只计算可路由的地址，作为百分比的分子
+    country_shares: BTreeMap<String, Count>,
+    summary_shares: Vec<Count>,
+}
+
+// proxifier 的每个地址段和 clash 的每条 IP-CIDR 等各算一条规则
+pub fn rule_count(content: &str) -> usize {
+    diff::output_ranges(content, "").map_or(0, |x| x.len())
+}
+
+impl Stats {
+    pub fn new(list: &[IpLocation], selected: &[(u128, u128)], reserved: &[ip_tool::SpecialPurpose], ipv6: bool, outputs: &[(&str, &str)]) -> error::Result<Stats> {
+        let unroutable = extract::subtract(&[(0, u128::MAX)], &routable_ranges(ipv6));
+        // 百分比的分子只计算可路由的部分
+        let share = |ranges: &[(u128, u128)]| {
+            let mut count = Count::default();
+            for (start, end) in extract::subtract(ranges, &unroutable) {
+                count.add(start, end, ipv6);
+            }
+            count
+        };
+        let mut countries: BTreeMap<String, Count> = BTreeMap::new();
+        let mut country_ranges: BTreeMap<&str, Vec<(u128, u128)>> = BTreeMap::new();
+        for row in list {
+            let (start, end) = row.range()?;
+            countries.entry(row.country_code.clone()).or_default().add(start, end, ipv6);
+            country_ranges.entry(&row.country_code).or_default().push((start, end));
+        }
+        let country_shares = country_ranges.into_iter().map(|(code, ranges)| (code.to_string(), share(&ranges))).collect();
+        let routable = routable();
+        let mut reserved_count = Count::default();
+        let reserved_ranges = if ipv6 {
+            ip_tool::special_purpose_ranges_ipv6(reserved)
+        } else {
+            ip_tool::special_purpose_ranges_ipv4(reserved).into_iter().map(|(start, end)| (start as u128, end as u128)).collect()
+        };
+        // 特殊用途的地址段之间有重叠，先合并
+        let reserved_ranges = extract::union(&reserved_ranges);
+        for &(start, end) in &reserved_ranges {
+            reserved_count.add(start, end, ipv6);
+        }
+        let mut selected_count = Count::default();
+        for &(start, end) in selected {
+            selected_count.add(start, end, ipv6);
+        }
+        let routable_count = Count { ipv4_addresses: routable.0, ipv6_addresses: routable.1, ..Count::default() };
+        let summary_shares = vec![routable_count.clone(), share(&reserved_ranges), share(selected)];
+        let summary = vec![
+            ("routable".to_string(), routable_count),
+            ("reserved".to_string(), reserved_count),
+            ("selected".to_string(), selected_count),
+        ];
+        Ok(Stats {
+            countries,
+            summary,
+            rules: outputs.iter().map(|(name, content)| (name.to_string(), rule_count(content))).collect(),
+            routable,
+            country_shares,
+            summary_shares,
+        })
+    }
+
+    // 可路由部分占可路由地址空间的百分比
+    fn percent(&self, share: &Count) -> (String, String) {
+        let percent = |x: u128, total: u128| format!("{:.4}", if total == 0 { 0.0 } else { x as f64 * 100.0 / total as f64 });
+        (percent(share.ipv4_addresses, self.routable.0), percent(share.ipv6_addresses, self.routable.1))
+    }
+
+    fn rows(&self) -> Vec<(&str, &Count, &Count)> {
+        self.countries.iter().map(|(code, x)| (code.as_str(), x, &self.country_shares[code]))
+            .chain(self.summary.iter().zip(&self.summary_shares).map(|((name, x), share)| (name.as_str(), x, share)))
+            .collect()
+    }
+
+    pub fn format_table(&self) -> String {
+        let mut result = format!("{:<10}{:>12}{:>14}{:>10}{:>12}{:>42}{:>10}\n", "country", "v4 ranges", "v4 addresses", "v4 %", "v6 ranges", "v6 addresses", "v6 %");
+        for (i, (name, x, share)) in self.rows().into_iter().enumerate() {
+            if i == self.countries.len() {
+                result.push('\n');
+            }
+            let (ipv4_percent, ipv6_percent) = self.percent(share);
+            result.push_str(&format!("{:<10}{:>12}{:>14}{:>10}{:>12}{:>42}{:>10}\n",
+                name, x.ipv4_ranges, x.ipv4_addresses, ipv4_percent, x.ipv6_ranges, x.ipv6_addresses, ipv6_percent));
+        }
+        result.push('\n');
+        for (name, count) in &self.rules {
+            result.push_str(&format!("{}: {} rules\n", name, count));
+        }
+        result
+    }
+
+    // 汇总行的国家代码为 *routable 这样的名称，规则条数为 rules:<文件名>
+    pub fn format_csv(&self) -> String {
+        let mut result = "country,ipv4_ranges,ipv4_addresses,ipv4_percent,ipv6_ranges,ipv6_addresses,ipv6_percent\n".to_string();
+        for (i, (name, x, share)) in self.rows().into_iter().enumerate() {
+            let name = if i < self.countries.len() { name.to_string() } else { format!("*{}", name) };
+            let (ipv4_percent, ipv6_percent) = self.percent(share);
+            result.push_str(&format!("{},{},{},{},{},{},{}\n", name, x.ipv4_ranges, x.ipv4_addresses, ipv4_percent, x.ipv6_ranges, x.ipv6_addresses, ipv6_percent));
+        }
+        for (name, count) in &self.rules {
+            result.push_str(&format!("rules:{},{},,,,,\n", name, count));
+        }
+        result
+    }
+
+    // 地址个数可能超出 JSON 数字的范围，统一输出为字符串
+    pub fn to_json(&self) -> serde_json::Value {
+        let row = |name: &str, x: &Count, share: &Count| {
+            let (ipv4_percent, ipv6_percent) = self.percent(share);
+            json!({
+                "name": name,
+                "ipv4_ranges": x.ipv4_ranges,
+                "ipv4_addresses": x.ipv4_addresses.to_string(),
+                "ipv4_percent": ipv4_percent,
+                "ipv6_ranges": x.ipv6_ranges,
+                "ipv6_addresses": x.ipv6_addresses.to_string(),
+                "ipv6_percent": ipv6_percent,
+            })
+        };
+        json!({
+            "countries": self.countries.iter().map(|(code, x)| row(code, x, &self.country_shares[code])).collect::<Vec<_>>(),
+            "summary": self.summary.iter().zip(&self.summary_shares).map(|((name, x), share)| row(name, x, share)).collect::<Vec<_>>(),
+            "rules": self.rules.iter().map(|(name, count)| (name.clone(), json!(count))).collect::<serde_json::Map<_, _>>(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod stats_test {
+    use crate::{extract::row, ip_tool::SpecialPurpose, stats::*};
+
+    #[test]
+    fn routable_test() {
+        let (ipv4, ipv6) = routable();
+        // 去掉 0/8、10/8、127/8、224/4、240/4 等之后大约 37 亿
+        assert!(ipv4 > 3_600_000_000 && ipv4 < 3_800_000_000, "{}", ipv4);
+        assert!(ipv6 < 1 << 125 && ipv6 > 1 << 124, "{}", ipv6);
+    }
+
+    #[test]
+    fn stats_ipv4_test() {
+        let list = [row(16777216, 16777471, "US"), row(16777472, 16778239, "CN"), row(16778240, 16779263, "US")];
+        let outputs = [("proxifier.txt", "\t\t\t<Targets>1.0.0.0-1.0.0.255;1.0.4.0-1.0.7.255;</Targets>\n"), ("clash.txt", "  - GEOIP,LAN,DIRECT\n  - IP-CIDR,1.0.0.0/24,auto\n  - IP-CIDR,1.0.4.0/22,auto\n  - MATCH,DIRECT\n")];
+        let stats = Stats::new(&list, &[(16777216, 16777471), (16778240, 16779263)], &[SpecialPurpose::Private], false, &outputs).unwrap();
+        assert_eq!(Count { ipv4_ranges: 2, ipv4_addresses: 1280, ..Count::default() }, stats.countries["US"]);
+        assert_eq!(Count { ipv4_ranges: 1, ipv4_addresses: 768, ..Count::default() }, stats.countries["CN"]);
+        // 10/8、172.16/12、192.168/16
+        assert_eq!(Count { ipv4_ranges: 3, ipv4_addresses: 16777216 + 1048576 + 65536, ..Count::default() }, stats.summary[1].1);
+        assert_eq!(stats.countries["US"], stats.summary[2].1);
+        assert_eq!(vec![("proxifier.txt".to_string(), 2), ("clash.txt".to_string(), 2)], stats.rules);
+
+        let csv = stats.format_csv();
+        assert!(csv.starts_with("country,ipv4_ranges,"));
+        assert!(csv.contains("\nUS,2,1280,0.0000,0,0,0.0000\n"), "{}", csv);
+        assert!(csv.contains("\n*selected,2,1280,"));
+        assert!(csv.contains("\nrules:clash.txt,2,,,,,\n"));
+        assert!(stats.format_table().contains("clash.txt: 2 rules"));
+        let value = stats.to_json();
+        assert_eq!("1280", value["countries"][1]["ipv4_addresses"]);
+        assert_eq!("routable", value["summary"][0]["name"]);
+        assert_eq!(2, value["rules"]["proxifier.txt"]);
+    }
+
+    #[test]
+    fn percent_test() {
+        // 0/8 和 10/8 都是特殊用途地址，不计入百分比的分子
+        let list = [row(0, 0x00ff_ffff, "-"), row(0x0a00_0000, 0x0aff_ffff, "ZZ"), row(0x0b00_0000, 0x0bff_ffff, "US")];
+        let stats = Stats::new(&list, &[(0x0a00_0000, 0x0bff_ffff)], &[SpecialPurpose::Private], false, &[]).unwrap();
+        let csv = stats.format_csv();
+        let percent = format!("{:.4}", 16777216.0 * 100.0 / routable().0 as f64);
+        assert!(csv.contains("\n-,1,16777216,0.0000,"), "{}", csv);
+        assert!(csv.contains("\nZZ,1,16777216,0.0000,"), "{}", csv);
+        assert!(csv.contains(&format!("\nUS,1,16777216,{},", percent)), "{}", csv);
+        assert!(csv.contains("\n*routable,0,") && csv.contains(",100.0000,0,"), "{}", csv);
+        assert!(csv.contains("\n*reserved,3,17891328,0.0000,"), "{}", csv);
+        assert!(csv.contains(&format!("\n*selected,1,33554432,{},", percent)), "{}", csv);
+    }
+
+    #[test]
+    fn stats_ipv6_test() {
+        let list = [
+            row(0, 281470681743359, "-"),
+            row(281470698520576, 281470698520831, "US"),
+            row(281470698520832, 340282366920938463463374607431768211455, "JP"),
+        ];
+        let stats = Stats::new(&list, &[], &[], true, &[]).unwrap();
+        assert_eq!(Count { ipv4_ranges: 1, ipv4_addresses: 256, ..Count::default() }, stats.countries["US"]);
+        let jp = &stats.countries["JP"];
+        assert_eq!((1, 1), (jp.ipv4_ranges, jp.ipv6_ranges));
+        assert_eq!(u32::MAX as u128 + 1 - 16777472, jp.ipv4_addresses);
+        assert_eq!(u128::MAX - 0xffff_ffff_ffff, jp.ipv6_addresses);
+        assert_eq!(Count { ipv6_ranges: 1, ipv6_addresses: 0xffff_0000_0000, ..Count::default() }, stats.countries["-"]);
+        assert_eq!(Count::default(), stats.summary[1].1);
+    }
+}